@@ -12,6 +12,7 @@ static mut HEAP: Heap<HEAP_SIZE> = Heap([0u8; HEAP_SIZE]);
 
 // This allocator can't work in tests since it's non-threadsafe.
 #[cfg_attr(not(test), global_allocator)]
+#[allow(static_mut_refs)]
 static ALLOC: NonThreadsafeAlloc = unsafe {
     let fast_param = FastAllocParam::new(FAST_HEAP.0.as_ptr(), FAST_HEAP_SIZE);
     let buddy_param = BuddyAllocParam::new(HEAP.0.as_ptr(), HEAP_SIZE, LEAF_SIZE);
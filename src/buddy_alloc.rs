@@ -6,16 +6,43 @@
 
 #![allow(clippy::needless_range_loop)]
 
+use crate::corruption::HeapCorruption;
+use crate::poison::Poison;
+use crate::zeroize::zeroize;
+use core::ops::Range;
+use core::ptr::NonNull;
+
 const OOM_MSG: &str = "requires more memory space to initialize BuddyAlloc";
-const LEAF_ALIGN_ERROR_MSG: &str = "leaf size must be align to 16 bytes";
-/// required align to 16 bytes, since Node takes 16 bytes on 64-bits machine.
-pub const MIN_LEAF_SIZE_ALIGN: usize = 16;
+const OVERFLOW_MSG: &str = "memory range overflows the address space";
+const LEAF_ALIGN_ERROR_MSG: &str = "leaf size must be align to the size of two pointers";
+/// required align to the size of Node, which is two pointers:
+/// 16 bytes on 64-bits machine, 8 bytes on 32-bits and 4 bytes on 16-bits.
+pub const MIN_LEAF_SIZE_ALIGN: usize = NODE_SIZE;
+
+/// Characters of `BuddyAlloc::write_map`
+pub const MAP_FREE: char = '.';
+pub const MAP_ALLOCATED: char = '#';
+/// The cell is covered by more than one block
+pub const MAP_SPLIT: char = '+';
+pub const MAP_METADATA: char = 'M';
+/// Cells per line of `BuddyAlloc::write_map`
+pub const MAP_COLUMNS: usize = 64;
 
+/// Returns the bytes of an order k block, panics if the size overflows usize,
+/// i.e. `k + log2(leaf_size)` must be below the pointer width.
 pub const fn block_size(k: usize, leaf_size: usize) -> usize {
-    (1 << k) * leaf_size
+    if k >= usize::BITS as usize {
+        panic!("block size overflows usize");
+    }
+    match (1usize << k).checked_mul(leaf_size) {
+        Some(size) => size,
+        None => panic!("block size overflows usize"),
+    }
 }
 
+/// orders of a heap are bounded by the pointer width, see `BuddyAlloc::new`
 const fn block_size_2base(k: usize, leaf2base: usize) -> usize {
+    debug_assert!(k + leaf2base < usize::BITS as usize);
     (1 << k) << leaf2base
 }
 
@@ -63,13 +90,46 @@ fn bit_clear(bit_array: *mut u8, i: usize) {
 
 // find a min k that great than n bytes
 pub fn first_up_k(n: usize, leaf_size: usize) -> usize {
-    let mut k = 0;
-    let mut size = leaf_size;
-    while size < n {
-        k += 1;
-        size <<= 1;
+    if n <= leaf_size {
+        return 0;
     }
-    k
+    // ceil(log2(ceil(n / leaf_size))), shifting leaf_size up may overflow on huge n
+    let nleaves = (n - 1) / leaf_size + 1;
+    (usize::BITS - (nleaves - 1).leading_zeros()) as usize
+}
+
+/// Returns the offsets of an address range from base, parts before base are cut off
+fn offsets(range: &Range<usize>, base: usize) -> Range<usize> {
+    range.start.saturating_sub(base)..range.end.saturating_sub(base)
+}
+
+/// Returns the bytes of the metadata, see `BuddyAlloc::new`
+fn metadata_size(entries_size: usize, exact_alloc: bool) -> usize {
+    // use one bit for per memory block
+    let bitmap_size = |k| roundup(nblock(k, entries_size), 3) >> 3;
+    let mut size = (core::mem::size_of::<Entry>() + NODE_SIZE) * entries_size;
+    // alloc and split
+    size += (0..entries_size).map(bitmap_size).sum::<usize>();
+    size += (1..entries_size).map(bitmap_size).sum::<usize>();
+    // extent
+    if exact_alloc {
+        size += bitmap_size(0);
+    }
+    size
+}
+
+/// How much of a block is usable, see `BuddyAlloc::init_free_list`
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Usage {
+    Full,
+    Partial,
+    Empty,
+}
+
+/// Blocks found by `BuddyAlloc::check_invariants`, which are not split
+struct TreeWalk {
+    free_blocks: [usize; usize::BITS as usize],
+    free_bytes: usize,
 }
 
 struct Node {
@@ -77,6 +137,13 @@ struct Node {
     prev: *mut Node,
 }
 
+const NODE_SIZE: usize = core::mem::size_of::<Node>();
+
+/// Free blocks known to be zero filled are marked with this value right after the node header
+const CLEAN_MARK: usize = 0xC1EA;
+/// The dirty bytes of a clean block
+const CLEAN_HEADER_SIZE: usize = NODE_SIZE + core::mem::size_of::<usize>();
+
 impl Node {
     fn init(list: *mut Node) {
         unsafe {
@@ -110,7 +177,7 @@ impl Node {
                 prev: list,
                 next: (*list).next,
             };
-            // pointer aligned to 16 bytes(MIN_LEAF_SIZE_ALIGN), so it's safe to use write
+            // pointer aligned to MIN_LEAF_SIZE_ALIGN, so it's safe to use write
             p.write(n_list);
             // To prevent the compiler from optimizing alias potiners
             // details https://github.com/jjyr/buddy-alloc/issues/16
@@ -119,8 +186,31 @@ impl Node {
         }
     }
 
+    /// Insert p before the first node which is after p in the address order
+    fn push_ordered(list: *mut Node, p: *mut u8, ascending: bool) {
+        let mut prev = list;
+        unsafe {
+            let mut node = (*list).next;
+            while !core::ptr::eq(node, list) && ((node as usize) < (p as usize)) == ascending {
+                prev = node;
+                node = (*node).next;
+            }
+        }
+        Self::push(prev, p);
+    }
+
     fn is_empty(list: *const Node) -> bool {
-        unsafe { (*list).next as *const Node == list }
+        unsafe { core::ptr::eq((*list).next, list) }
+    }
+
+    fn len(list: *const Node) -> usize {
+        let mut n = 0;
+        let mut node = unsafe { (*list).next };
+        while !core::ptr::eq(node, list) {
+            n += 1;
+            node = unsafe { (*node).next };
+        }
+        n
     }
 }
 
@@ -142,6 +232,60 @@ impl Default for Entry {
     }
 }
 
+/// A snapshot of the usage of a BuddyAlloc
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Stats {
+    /// Bytes managed by the allocator, see `BuddyAlloc::available_bytes`
+    pub available_bytes: usize,
+    /// Bytes of all free blocks
+    pub free_bytes: usize,
+    /// Size of the largest free block, the largest request can be served
+    pub largest_free_block: usize,
+    /// Number of free blocks, the free bytes are more fragmented if they are split into more blocks
+    pub free_blocks: usize,
+}
+
+impl core::fmt::Display for Stats {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "{} of {} bytes free, largest free block {} bytes",
+            self.free_bytes, self.available_bytes, self.largest_free_block
+        )
+    }
+}
+
+/// Why a range can't be reserved, see `BuddyAlloc::reserve`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReserveError {
+    /// The range is not inside the memory range of the heap
+    OutOfHeap,
+    /// Some leaves covering the range are in use
+    InUse,
+}
+
+impl core::fmt::Display for ReserveError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            ReserveError::OutOfHeap => write!(f, "range is out of the heap"),
+            ReserveError::InUse => write!(f, "range is in use"),
+        }
+    }
+}
+
+/// Which free block serves a request, when several blocks of the order are free
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Placement {
+    /// The most recently freed block, the default behavior
+    #[default]
+    Lifo,
+    /// The block of the lowest address, split blocks keep their lower halves.
+    /// Long-lived blocks gather at the start of the heap, leaving large blocks at the end.
+    LowestAddress,
+    /// The block of the highest address, split blocks keep their upper halves
+    HighestAddress,
+}
+
 #[derive(Clone, Copy)]
 pub struct BuddyAllocParam {
     /// Base addr: the start address
@@ -153,6 +297,14 @@ pub struct BuddyAllocParam {
     /// Zero filled: in many cases, provided address might already be zero filled,
     /// in which case we can reduce re-filling zeros to the data again.
     zero_filled: bool,
+    /// Poison: fill blocks with patterns on alloc and free
+    poison: Option<Poison>,
+    /// Zeroize: wipe blocks on free
+    zeroize: bool,
+    /// Placement: which free block serves a request
+    placement: Placement,
+    /// Exact alloc: reserve a bit per leaf to record extents of `alloc_exact` blocks
+    exact_alloc: bool,
 }
 
 impl BuddyAllocParam {
@@ -165,6 +317,10 @@ impl BuddyAllocParam {
             len,
             leaf_size,
             zero_filled: false,
+            poison: None,
+            zeroize: false,
+            placement: Placement::Lifo,
+            exact_alloc: false,
         }
     }
 
@@ -175,21 +331,77 @@ impl BuddyAllocParam {
             len,
             leaf_size,
             zero_filled: true,
+            poison: None,
+            zeroize: false,
+            placement: Placement::Lifo,
+            exact_alloc: false,
         }
     }
+
+    /// Debug mode: poison blocks on alloc and free, see `Poison`
+    pub const fn with_poison(mut self, poison: Poison) -> Self {
+        self.poison = Some(poison);
+        self
+    }
+
+    /// Secure mode: wipe every freed block, so the freed memory does not keep secrets.
+    /// The full block is wiped, not just the requested bytes.
+    pub const fn with_zeroize(mut self) -> Self {
+        self.zeroize = true;
+        self
+    }
+
+    /// Placement policy, see `Placement`
+    pub const fn with_placement(mut self, placement: Placement) -> Self {
+        self.placement = placement;
+        self
+    }
+
+    /// Enable `BuddyAlloc::alloc_exact`, which costs a bit per leaf of metadata
+    pub const fn with_exact_alloc(mut self) -> Self {
+        self.exact_alloc = true;
+        self
+    }
 }
 
+/// Buddy allocator
+///
+/// # Worst-case bounds
+///
+/// Let `K` be the number of orders, `log2(heap size / leaf size) + 1`, which is less than
+/// `usize::BITS`. Every step below is a constant number of bit and list operations:
+///
+/// * `malloc`: one `trailing_zeros` on the non-empty orders mask, then at most `K - 1` splits.
+/// * `free`: at most `ceil(log2(K + 1))` split bit probes to find the order of the block,
+///   then at most `K - 1` merges.
+/// * `alloc_order` and `free_order` are `malloc` and `free` without the order lookup.
+/// * `alloc_exact` splits at most `K - 1` times after `malloc`, freeing its block repeats `free`
+///   for each of at most `K` pieces.
+///
+/// Poison and zeroize modes add a pass over the block, `stats` walks all free lists.
+/// The address-ordered placements keep free lists sorted, which adds a walk of the free list
+/// to every push, so they are bounded by the number of free blocks instead.
 pub struct BuddyAlloc {
     /// memory start addr
     base_addr: usize,
-    /// memory end addr
-    end_addr: usize,
-    /// unavailable memories at end_addr
+    /// memory bytes from base_addr
+    len: usize,
+    /// unavailable memories at the end and in the holes
     unavailable: usize,
     entries: *mut Entry,
     entries_size: usize,
+    /// bit k is set if the free list of order k is not empty
+    nonempty: usize,
     /// min size of a block, represent in 1 << leaf2base
     leaf2base: usize,
+    poison: Option<Poison>,
+    zeroize: bool,
+    placement: Placement,
+    /// Bit array per leaf, set if the leaf starts a piece continuing the block before it,
+    /// see `alloc_exact`. Null if exact allocations are disabled.
+    extent: *mut u8,
+    /// tracking zero filled free blocks, see `malloc_zeroed`
+    track_clean: bool,
 }
 
 impl BuddyAlloc {
@@ -199,44 +411,98 @@ impl BuddyAlloc {
     /// and must guarantee no others write to the memory range, to avoid undefined behaviors.
     /// The new function panic if memory space not enough for initialize BuddyAlloc.
     pub unsafe fn new(param: BuddyAllocParam) -> Self {
+        Self::new_with_holes(param, &[])
+    }
+
+    /// Similar to new, but the address ranges of holes are excluded, e.g. MMIO or ROM shadows
+    /// of a firmware memory map. The metadata is put in the first usable piece large enough.
+    /// Holes may be unaligned, the leaves overlapping them are never allocated.
+    /// Holes are only read here, they may be dropped once the allocator is built.
+    ///
+    /// # Safety
+    ///
+    /// See `BuddyAlloc::new`, the holes are never written.
+    pub unsafe fn new_with_holes(param: BuddyAllocParam, holes: &[Range<usize>]) -> Self {
         let BuddyAllocParam {
             base_addr,
             len,
             leaf_size,
             zero_filled,
+            poison,
+            zeroize,
+            placement,
+            exact_alloc,
         } = param;
-        let mut base_addr = base_addr as usize;
-        let end_addr = base_addr + len;
+        let region = base_addr as usize;
+        // the region may end at the top of the address space, so `region + len` may overflow,
+        // memory is measured by offsets from the region start instead of end addrs.
+        assert!(
+            len == 0 || region.checked_add(len - 1).is_some(),
+            "{}",
+            OVERFLOW_MSG
+        );
         assert!(
             leaf_size % MIN_LEAF_SIZE_ALIGN == 0 && leaf_size != 0,
             "{}",
             LEAF_ALIGN_ERROR_MSG
         );
         let leaf2base = log2(leaf_size);
-        base_addr = roundup(base_addr, leaf2base);
+        // bytes to the next leaf aligned addr, 2^N wraps to 0 which is aligned
+        let align_offset =
+            |offset: usize| region.wrapping_add(offset).wrapping_neg() & (leaf_size - 1);
+        let heap_offset = align_offset(0);
+        assert!(len >= heap_offset + leaf_size, "{}", OOM_MSG);
         // we use (k + 1)-th entry's split flag to test existence of k-th entry's blocks;
         // to accoding this convention, we make a dummy (entries_size - 1)-th entry.
         // so we plus 2 on entries_size.
-        let entries_size = log2((end_addr - base_addr) >> leaf2base) + 2;
+        let entries_size = log2((len - heap_offset) >> leaf2base) + 2;
+        debug_assert!(entries_size <= usize::BITS as usize);
+
+        // without holes the metadata is at the start and the heap follows it,
+        // otherwise the heap covers the whole range and the metadata is excluded like holes.
+        let metadata_start = if holes.is_empty() {
+            heap_offset
+        } else {
+            let size = metadata_size(entries_size, exact_alloc);
+            let fits = |start: usize| {
+                len.checked_sub(start).is_some_and(|rest| rest >= size)
+                    && !holes.iter().any(|hole| {
+                        let hole = offsets(hole, region);
+                        hole.start < hole.end
+                            && hole.end > start
+                            && (hole.start <= start || hole.start - start < size)
+                    })
+            };
+            // the start of the range or the end of a hole
+            core::iter::once(heap_offset)
+                .chain(holes.iter().map(|hole| {
+                    let end = offsets(hole, region).end;
+                    end + align_offset(end)
+                }))
+                .filter(|&start| fits(start))
+                .min()
+                .expect(OOM_MSG)
+        };
+        let mut offset = metadata_start;
 
         // alloc buddy allocator memory
         let used_bytes = core::mem::size_of::<Entry>() * entries_size;
-        debug_assert!(end_addr >= base_addr + used_bytes, "{}", OOM_MSG);
-        let entries = base_addr as *mut Entry;
-        base_addr += used_bytes;
+        assert!(len - offset >= used_bytes, "{}", OOM_MSG);
+        let entries = (region + offset) as *mut Entry;
+        offset += used_bytes;
 
         let buddy_list_size = core::mem::size_of::<Node>();
         // init entries free
         for k in 0..entries_size {
             // use one bit for per memory block
-            debug_assert!(end_addr >= base_addr + buddy_list_size, "{}", OOM_MSG);
+            assert!(len - offset >= buddy_list_size, "{}", OOM_MSG);
             let entry = entries.add(k).as_mut().expect("entry");
-            entry.free = base_addr as *mut Node;
+            entry.free = (region + offset) as *mut Node;
             if !zero_filled {
                 core::ptr::write_bytes(entry.free, 0, buddy_list_size);
             }
             Node::init(entry.free);
-            base_addr += buddy_list_size;
+            offset += buddy_list_size;
         }
 
         // init alloc
@@ -244,14 +510,14 @@ impl BuddyAlloc {
             // use one bit for per memory block
             // use shift instead `/`, 8 == 1 << 3
             let used_bytes = roundup(nblock(k, entries_size), 3) >> 3;
-            debug_assert!(end_addr >= base_addr + used_bytes, "{}", OOM_MSG);
+            assert!(len - offset >= used_bytes, "{}", OOM_MSG);
             let entry = entries.add(k).as_mut().expect("entry");
-            entry.alloc = base_addr as *mut u8;
+            entry.alloc = (region + offset) as *mut u8;
             // mark all blocks as allocated
             if !zero_filled {
                 core::ptr::write_bytes(entry.alloc, 0, used_bytes);
             }
-            base_addr += used_bytes;
+            offset += used_bytes;
         }
 
         // init split
@@ -259,18 +525,45 @@ impl BuddyAlloc {
             // use one bit for per memory block
             // use shift instead `/`, 8 == 1 << 3
             let used_bytes = roundup(nblock(k, entries_size), 3) >> 3;
-            debug_assert!(end_addr >= base_addr + used_bytes, "{}", OOM_MSG);
+            assert!(len - offset >= used_bytes, "{}", OOM_MSG);
             let entry = entries.add(k).as_mut().expect("entry");
-            entry.split = base_addr as *mut u8;
+            entry.split = (region + offset) as *mut u8;
             if !zero_filled {
                 core::ptr::write_bytes(entry.split, 0, used_bytes);
             }
-            base_addr += used_bytes;
+            offset += used_bytes;
         }
 
-        // align base_addr to leaf size
-        base_addr = roundup(base_addr, leaf2base);
-        assert!(end_addr >= base_addr, "{}", OOM_MSG);
+        // init extent
+        let extent = if exact_alloc {
+            // use one bit for per leaf
+            let used_bytes = roundup(nblock(0, entries_size), 3) >> 3;
+            assert!(len - offset >= used_bytes, "{}", OOM_MSG);
+            let extent = (region + offset) as *mut u8;
+            if !zero_filled {
+                core::ptr::write_bytes(extent, 0, used_bytes);
+            }
+            offset += used_bytes;
+            extent
+        } else {
+            core::ptr::null_mut()
+        };
+
+        debug_assert_eq!(
+            offset - metadata_start,
+            metadata_size(entries_size, exact_alloc)
+        );
+        let metadata = if holes.is_empty() {
+            // align base_addr to leaf size, there must be space left to address the heap
+            offset += align_offset(offset);
+            assert!(len > offset, "{}", OOM_MSG);
+            0..0
+        } else {
+            let metadata = (metadata_start - heap_offset)..(offset - heap_offset);
+            offset = heap_offset;
+            metadata
+        };
+        let base_addr = region + offset;
         debug_assert_eq!(
             (base_addr >> leaf2base) << leaf2base,
             base_addr,
@@ -279,69 +572,337 @@ impl BuddyAlloc {
 
         let mut allocator = BuddyAlloc {
             base_addr,
-            end_addr,
+            len: len - offset,
             entries,
             entries_size,
+            nonempty: 0,
             leaf2base,
             unavailable: 0,
+            poison,
+            zeroize,
+            placement,
+            extent,
+            // only tracking clean blocks if blocks are zero filled initially or on free
+            track_clean: (zero_filled || zeroize) && poison.is_none(),
         };
-        allocator.init_free_list();
+        allocator.init_free_list(zero_filled, holes, metadata);
         allocator
     }
 
-    fn init_free_list(&mut self) {
-        let mut base_addr = self.base_addr;
-        let end_addr = self.end_addr;
-        let entries_size = self.entries_size;
+    /// Blocks are pushed to the free lists if they are fully usable, marked as allocated if
+    /// they are fully excluded by the holes, the metadata or the end of the heap,
+    /// otherwise marked as split and both halves are initialized in the same way.
+    fn init_free_list(
+        &mut self,
+        zero_filled: bool,
+        holes: &[Range<usize>],
+        metadata: Range<usize>,
+    ) {
+        // the dummy top order is always split
+        let top = self.entries_size - 1;
+        bit_set(self.entry(top).alloc, 0);
+        bit_set(self.entry(top).split, 0);
+        let half = block_size_2base(top - 1, self.leaf2base);
+        let free_bytes = self.init_block(top - 1, 0, zero_filled, holes, &metadata)
+            + self.init_block(top - 1, half, zero_filled, holes, &metadata);
+        self.unavailable = self.len - free_bytes;
+    }
 
-        // try alloc blocks
-        for k in (0..(entries_size - 1)).rev() {
-            let block_size = block_size_2base(k, self.leaf2base);
-            let entry = self.entry(k);
-            let parent_entry = self.entry(k + 1);
-
-            // alloc free blocks
-            while base_addr + block_size <= end_addr {
-                debug_assert!(!bit_isset(
-                    entry.alloc,
-                    self.block_index(k, base_addr as *const u8)
-                ));
-                Node::push(entry.free, base_addr as *mut u8);
-                // mark parent's split and alloc
-                let block_index = self.block_index(k, base_addr as *const u8);
-                if block_index & 1 == 0 {
-                    let parent_index = self.block_index(k + 1, base_addr as *const u8);
-                    bit_set(parent_entry.alloc, parent_index);
-                    bit_set(parent_entry.split, parent_index);
+    /// init the order k block at offset, returns the free bytes in it
+    fn init_block(
+        &mut self,
+        k: usize,
+        offset: usize,
+        zero_filled: bool,
+        holes: &[Range<usize>],
+        metadata: &Range<usize>,
+    ) -> usize {
+        let block_size = block_size_2base(k, self.leaf2base);
+        // equal to: block_index(k, base_addr + offset), which may overflow
+        let block_index = (offset >> k) >> self.leaf2base;
+        match self.usage(k, offset, holes, metadata) {
+            Usage::Full => {
+                let p = (self.base_addr + offset) as *mut u8;
+                if let Some(poison) = self.poison {
+                    poison.fill_free(p, block_size);
                 }
-                base_addr += block_size;
+                self.push_free(k, p);
+                self.set_clean(k, p, zero_filled);
+                block_size
+            }
+            Usage::Empty => {
+                bit_set(self.entry(k).alloc, block_index);
+                0
+            }
+            Usage::Partial => {
+                // split bits of the blocks containing any block are set, see find_k_for_p
+                bit_set(self.entry(k).alloc, block_index);
+                bit_set(self.entry(k).split, block_index);
+                let half = block_size >> 1;
+                self.init_block(k - 1, offset, zero_filled, holes, metadata)
+                    + self.init_block(k - 1, offset + half, zero_filled, holes, metadata)
             }
-
-            // mark unavailable blocks as allocated
-            let n = nblock(k, entries_size);
-            let unavailable_block_index = self.block_index(k, base_addr as *const u8);
-            debug_assert!(unavailable_block_index < n);
-            bit_set(entry.alloc, unavailable_block_index);
         }
+    }
 
-        self.unavailable = end_addr - base_addr;
+    /// Returns how much of the order k block at offset is usable
+    fn usage(
+        &self,
+        k: usize,
+        offset: usize,
+        holes: &[Range<usize>],
+        metadata: &Range<usize>,
+    ) -> Usage {
+        if offset >= self.len {
+            return Usage::Empty;
+        }
+        let block_size = block_size_2base(k, self.leaf2base);
+        let mut usage = if self.len - offset >= block_size {
+            Usage::Full
+        } else {
+            Usage::Partial
+        };
+        let excluded = holes
+            .iter()
+            .map(|hole| offsets(hole, self.base_addr))
+            .chain(core::iter::once(metadata.clone()));
+        for range in excluded {
+            if range.start >= range.end
+                || range.end <= offset
+                || (range.start > offset && range.start - offset >= block_size)
+            {
+                continue;
+            }
+            if range.start <= offset && range.end - offset >= block_size {
+                return Usage::Empty;
+            }
+            usage = Usage::Partial;
+        }
+        // a leaf is never split, it's excluded if any byte of it is
+        if k == 0 && usage == Usage::Partial {
+            return Usage::Empty;
+        }
+        usage
     }
 
     pub fn malloc(&mut self, nbytes: usize) -> *mut u8 {
-        let fk = first_up_k(nbytes, 1 << self.leaf2base);
-        let mut k = match (fk..self.entries_size).find(|&k| !Node::is_empty(self.entry(k).free)) {
-            Some(k) => k,
+        self.malloc_inner(nbytes).0
+    }
+
+    /// Allocate nbytes at an address aligned to align, which must be a power of two.
+    /// Blocks are aligned to their sizes relative to the heap base, which is only aligned to
+    /// the leaf size. So unless the heap base is aligned, an align larger than the leaf size
+    /// takes `align - leaf size` more bytes and returns a pointer inside the block,
+    /// which `free` and `usable_size` accept.
+    pub fn malloc_aligned(&mut self, nbytes: usize, align: usize) -> *mut u8 {
+        debug_assert!(align.is_power_of_two());
+        let leaf_size = 1 << self.leaf2base;
+        if align <= leaf_size {
+            return self.malloc(nbytes);
+        }
+        if self.base_addr & (align - 1) == 0 {
+            // blocks of at least align bytes are aligned
+            return self.malloc(core::cmp::max(nbytes, align));
+        }
+        let nbytes = match nbytes.checked_add(align - leaf_size) {
+            Some(nbytes) => nbytes,
             None => return core::ptr::null_mut(),
         };
-        let p: *mut u8 = Node::pop(self.entry(k).free) as *mut u8;
+        let p = self.malloc(nbytes);
+        if p.is_null() {
+            return p;
+        }
+        // the block may end at the top of the address space
+        p.wrapping_add((p as usize).wrapping_neg() & (align - 1))
+    }
+
+    /// Returns the start of the order k block p points into
+    fn block_start(&self, k: usize, p: *const u8) -> *mut u8 {
+        let mask = block_size_2base(k, self.leaf2base) - 1;
+        (self.base_addr + ((p as usize - self.base_addr) & !mask)) as *mut u8
+    }
+
+    /// Similar to malloc, but the returned memory is zero filled.
+    /// Filling is skipped if the block is known to be zero filled,
+    /// which is the case for never used blocks of a `BuddyAllocParam::new_with_zero_filled` heap.
+    pub fn malloc_zeroed(&mut self, nbytes: usize) -> *mut u8 {
+        let (p, clean) = self.malloc_inner(nbytes);
+        if !p.is_null() {
+            // the node header is the only dirty part of a clean block
+            let dirty_bytes = if clean {
+                core::cmp::min(CLEAN_HEADER_SIZE, nbytes)
+            } else {
+                nbytes
+            };
+            unsafe { core::ptr::write_bytes(p, 0, dirty_bytes) };
+        }
+        p
+    }
+
+    /// Allocate a block of `block_size(order, leaf_size)` bytes, the order is not looked up by
+    /// `free_order`. Returns None if the order is out of the heap or no block is free.
+    pub fn alloc_order(&mut self, order: usize) -> Option<NonNull<u8>> {
+        if order >= self.entries_size - 1 {
+            return None;
+        }
+        let orders = self.nonempty >> order;
+        if orders == 0 {
+            return None;
+        }
+        NonNull::new(
+            self.alloc_from(order + orders.trailing_zeros() as usize, order)
+                .0,
+        )
+    }
+
+    /// Free a block of `alloc_order`, the order must be the allocated one
+    pub fn free_order(&mut self, p: NonNull<u8>, order: usize) {
+        debug_assert_eq!(
+            self.find_k_for_p(p.as_ptr()),
+            order,
+            "free with a wrong order"
+        );
+        self.free_block(p.as_ptr(), order);
+    }
+
+    /// Allocate `n_frames` contiguous frames, which are freed by `free`.
+    /// A power of two number of frames is allocated unless exact allocations are enabled,
+    /// see `alloc_exact`.
+    pub fn alloc_contiguous(&mut self, n_frames: usize) -> Option<NonNull<u8>> {
+        let nleaves = core::cmp::max(n_frames, 1);
+        let order = (usize::BITS - (nleaves - 1).leading_zeros()) as usize;
+        let p = self.alloc_order(order)?;
+        if !self.extent.is_null() {
+            self.split_tail(p.as_ptr(), order, nleaves);
+        }
+        Some(p)
+    }
+
+    /// Returns the frame size, frames are leaves of the heap
+    pub fn frame_size(&self) -> usize {
+        1 << self.leaf2base
+    }
+
+    /// Returns the frame number of p, which is the address in frames, e.g. the PFN of a page
+    pub fn frame_number(&self, p: *const u8) -> usize {
+        p as usize >> self.leaf2base
+    }
+
+    /// Returns the start address of a frame
+    pub fn frame_ptr(&self, frame_number: usize) -> *mut u8 {
+        (frame_number << self.leaf2base) as *mut u8
+    }
+
+    /// Similar to malloc, but the trailing buddies not needed to cover `nbytes` are freed,
+    /// so at most one leaf is wasted. The allocation is kept as pieces of decreasing orders,
+    /// `free` and `usable_size` follow the pieces by the extent bits.
+    /// Same as malloc if exact allocations are not enabled by `BuddyAllocParam::with_exact_alloc`.
+    pub fn alloc_exact(&mut self, nbytes: usize) -> *mut u8 {
+        let p = self.malloc(nbytes);
+        if p.is_null() || self.extent.is_null() {
+            return p;
+        }
+        let k = self.find_k_for_p(p);
+        // leaves to keep, malloc(0) takes a leaf
+        let nleaves = core::cmp::max(nbytes, 1).div_ceil(1 << self.leaf2base);
+        self.split_tail(p, k, nleaves);
+        p
+    }
+
+    /// split the allocated block p of order k into pieces covering the first `nleaves` leaves,
+    /// frees the rest.
+    fn split_tail(&mut self, p: *mut u8, mut k: usize, nleaves: usize) {
+        debug_assert!(nleaves > 0 && nleaves <= 1 << k);
+        let mut q = p;
+        let mut rest = nleaves;
+        // the order k block at q is allocated and must cover rest leaves
+        while rest < 1 << k {
+            let half = block_size_2base(k - 1, self.leaf2base);
+            let upper = (q as usize + half) as *mut u8;
+            bit_set(self.entry(k).split, self.block_index(k, q));
+            bit_set(self.entry(k - 1).alloc, self.block_index(k - 1, q));
+            if rest <= 1 << (k - 1) {
+                self.release(k - 1, upper);
+            } else {
+                // the lower half is a full piece, the upper half continues it
+                bit_set(self.entry(k - 1).alloc, self.block_index(k - 1, upper));
+                bit_set(self.extent, self.block_index(0, upper));
+                rest -= 1 << (k - 1);
+                q = upper;
+            }
+            k -= 1;
+        }
+    }
+
+    /// push a block cut from an allocated block, its buddy is allocated so there is no merge
+    fn release(&mut self, k: usize, p: *mut u8) {
+        if self.zeroize {
+            zeroize(p, block_size_2base(k, self.leaf2base));
+        }
+        if let Some(poison) = self.poison {
+            poison.fill_free(p, block_size_2base(k, self.leaf2base));
+        }
+        debug_assert!(!bit_isset(self.entry(k).alloc, self.block_index(k, p)));
+        self.push_free(k, p);
+        self.set_clean(k, p, self.zeroize);
+    }
+
+    /// malloc, also returns whether the block is clean
+    fn malloc_inner(&mut self, nbytes: usize) -> (*mut u8, bool) {
+        let fk = first_up_k(nbytes, 1 << self.leaf2base);
+        // the lowest non-empty order not below fk
+        let orders = self.nonempty.checked_shr(fk as u32).unwrap_or(0);
+        if orders == 0 {
+            return (core::ptr::null_mut(), false);
+        }
+        self.alloc_from(fk + orders.trailing_zeros() as usize, fk)
+    }
+
+    /// Similar to malloc, but returns the lowest free block which is below `limit`,
+    /// larger blocks are split to serve it. Returns null if there is no such block.
+    /// Free lists must be sorted by `Placement::LowestAddress`, so the heads are the lowest.
+    pub(crate) fn malloc_below(&mut self, nbytes: usize, limit: *const u8) -> *mut u8 {
+        debug_assert_eq!(self.placement, Placement::LowestAddress);
+        let fk = first_up_k(nbytes, 1 << self.leaf2base);
+        let mut orders = self.nonempty.checked_shr(fk as u32).unwrap_or(0) << fk;
+        let mut lowest = None;
+        while orders != 0 {
+            let k = orders.trailing_zeros() as usize;
+            orders &= orders - 1;
+            let head = unsafe { (*self.entry(k).free).next } as usize;
+            if head < limit as usize && lowest.is_none_or(|(_, addr)| head < addr) {
+                lowest = Some((k, head));
+            }
+        }
+        match lowest {
+            Some((k, _)) => self.alloc_from(k, fk).0,
+            None => core::ptr::null_mut(),
+        }
+    }
+
+    /// pop a free block of order k, then split it down to order fk
+    fn alloc_from(&mut self, mut k: usize, fk: usize) -> (*mut u8, bool) {
+        let mut p = self.pop_free(k);
+        if let Some(poison) = self.poison {
+            poison.check_freed(p, NODE_SIZE, block_size_2base(k, self.leaf2base));
+        }
+        let clean = self.is_clean(k, p);
         bit_set(self.entry(k).alloc, self.block_index(k, p));
         while k > fk {
-            let q: *mut u8 = (p as usize + block_size_2base(k - 1, self.leaf2base)) as *mut u8;
             bit_set(self.entry(k).split, self.block_index(k, p));
+            // keep the half on the side of the placement, free the other
+            let upper = (p as usize + block_size_2base(k - 1, self.leaf2base)) as *mut u8;
+            let q = if self.placement == Placement::HighestAddress {
+                core::mem::replace(&mut p, upper)
+            } else {
+                upper
+            };
             let parent_entry = self.entry(k - 1);
             bit_set(parent_entry.alloc, self.block_index(k - 1, p));
             debug_assert!(!bit_isset(parent_entry.alloc, self.block_index(k - 1, q)));
-            Node::push(parent_entry.free, q);
+            self.push_free(k - 1, q);
+            // the splitted half inherits the clean state
+            self.set_clean(k - 1, q, clean);
             k -= 1;
         }
         debug_assert_eq!(
@@ -349,11 +910,49 @@ impl BuddyAlloc {
             p as usize,
             "misalignment"
         );
-        p
+        if let Some(poison) = self.poison {
+            poison.fill_alloc(p, block_size_2base(fk, self.leaf2base));
+        }
+        (p, clean)
+    }
+
+    /// p may point inside the block, see `malloc_aligned`
+    pub fn free(&mut self, p: *mut u8) {
+        let k = self.find_k_for_p(p);
+        let p = self.block_start(k, p);
+        let mut next = self.next_piece(p, k);
+        self.free_block(p, k);
+        // the following pieces of an `alloc_exact` block
+        while let Some(q) = next {
+            let k = self.find_k_for_p(q);
+            bit_clear(self.extent, self.block_index(0, q));
+            next = self.next_piece(q, k);
+            self.free_block(q, k);
+        }
     }
 
-    pub fn free(&mut self, mut p: *mut u8) {
-        let mut k = self.find_k_for_p(p);
+    /// Returns the piece follows the order k piece p in the same allocation
+    fn next_piece(&self, p: *const u8, k: usize) -> Option<*mut u8> {
+        if self.extent.is_null() {
+            return None;
+        }
+        // offset from base_addr, base_addr + len may overflow
+        let offset = p as usize - self.base_addr + block_size_2base(k, self.leaf2base);
+        if offset >= self.len || !bit_isset(self.extent, offset >> self.leaf2base) {
+            return None;
+        }
+        Some((self.base_addr + offset) as *mut u8)
+    }
+
+    fn free_block(&mut self, mut p: *mut u8, mut k: usize) {
+        if self.zeroize {
+            zeroize(p, block_size_2base(k, self.leaf2base));
+        }
+        if let Some(poison) = self.poison {
+            poison.fill_free(p, block_size_2base(k, self.leaf2base));
+        }
+        // the freed block is clean only if it is wiped
+        let mut clean = self.zeroize;
         while k < (self.entries_size - 1) {
             let block_index = self.block_index(k, p);
             let entry = self.entry(k);
@@ -373,7 +972,17 @@ impl BuddyAlloc {
             // 3. repeat for k = k + 1 until reach MAX_K
             // 4. push p back to k entry free list
             let q = self.block_addr(k, buddy);
-            Node::remove(q as *mut Node);
+            self.remove_free(k, q as *mut u8);
+            // merged block is clean only if both halves are clean
+            clean = self.is_clean(k, q as *mut u8) && clean;
+            if clean {
+                // the header of buddy is inside the merged block now
+                unsafe { core::ptr::write_bytes(q as *mut u8, 0, CLEAN_HEADER_SIZE) };
+            }
+            if let Some(poison) = self.poison {
+                // the node header of buddy is inside the merged block now
+                poison.fill_free(q as *mut u8, NODE_SIZE);
+            }
             if !is_head {
                 p = q as *mut u8;
             }
@@ -381,7 +990,179 @@ impl BuddyAlloc {
             k += 1;
         }
         debug_assert!(!bit_isset(self.entry(k).alloc, self.block_index(k, p)));
-        Node::push(self.entry(k).free, p);
+        self.push_free(k, p);
+        self.set_clean(k, p, clean);
+    }
+
+    /// Returns the size of the block p points to, which may be larger than the requested bytes.
+    /// If p points inside the block, the bytes from p to the end of the block.
+    pub fn usable_size(&self, p: *const u8) -> usize {
+        let k = self.find_k_for_p(p);
+        let start = self.block_start(k, p);
+        let mut size = block_size_2base(k, self.leaf2base) - (p as usize - start as usize);
+        let p = start;
+        let mut next = self.next_piece(p, k);
+        while let Some(q) = next {
+            let k = self.find_k_for_p(q);
+            size += block_size_2base(k, self.leaf2base);
+            next = self.next_piece(q, k);
+        }
+        size
+    }
+
+    /// Try to grow the block p points to in place, by merging the free buddies that follow it.
+    /// Returns false and changes nothing if the block can't cover `nbytes` this way.
+    pub fn grow_in_place(&mut self, p: *mut u8, nbytes: usize) -> bool {
+        let k = self.find_k_for_p(p);
+        let fk = first_up_k(nbytes, 1 << self.leaf2base);
+        if fk <= k {
+            return true;
+        }
+        if fk >= self.entries_size - 1 {
+            return false;
+        }
+        // p must be the head of each merged block and every following buddy must be free
+        for j in k..fk {
+            let block_index = self.block_index(j, p);
+            if block_index & 1 == 1 || bit_isset(self.entry(j).alloc, block_index + 1) {
+                return false;
+            }
+        }
+        for j in k..fk {
+            let block_index = self.block_index(j, p);
+            let q = self.block_addr(j, block_index + 1) as *mut u8;
+            self.remove_free(j, q);
+            if let Some(poison) = self.poison {
+                poison.check_freed(q, NODE_SIZE, block_size_2base(j, self.leaf2base));
+                poison.fill_alloc(q, block_size_2base(j, self.leaf2base));
+            }
+            bit_clear(self.entry(j).alloc, block_index);
+            bit_clear(self.entry(j + 1).split, self.block_index(j + 1, p));
+        }
+        debug_assert!(bit_isset(self.entry(fk).alloc, self.block_index(fk, p)));
+        true
+    }
+
+    /// Shrink the block p points to in place, the trailing buddies not needed to cover
+    /// `nbytes` are freed at once and p stays valid. The block keeps a power of two size,
+    /// or at most one leaf of waste if exact allocations are enabled, see `alloc_exact`.
+    /// Does nothing if `nbytes` is not less than the usable size.
+    pub fn shrink(&mut self, p: *mut u8, nbytes: usize) {
+        let leaf_size = 1 << self.leaf2base;
+        // leaves to keep
+        let mut rest = if self.extent.is_null() {
+            1 << first_up_k(nbytes, leaf_size)
+        } else {
+            core::cmp::max(nbytes, 1).div_ceil(leaf_size)
+        };
+        let mut piece = Some(p);
+        while let Some(q) = piece {
+            let k = self.find_k_for_p(q);
+            piece = self.next_piece(q, k);
+            if rest >= 1 << k {
+                rest -= 1 << k;
+            } else if rest > 0 {
+                self.split_tail(q, k, rest);
+                rest = 0;
+            } else {
+                bit_clear(self.extent, self.block_index(0, q));
+                self.free_block(q, k);
+            }
+        }
+    }
+
+    /// Take the leaves covering `addr..(addr + len)` out of the heap, e.g. a framebuffer or a
+    /// DMA window fixed by hardware. The free blocks containing the range are split, so exactly
+    /// the covering leaves are allocated. Nothing is changed if the range can't be reserved.
+    /// Note the free list nodes are kept in free blocks, the contents of the range are not kept.
+    pub fn reserve(&mut self, addr: *const u8, len: usize) -> Result<(), ReserveError> {
+        let (start, end) = self.covering_leaves(addr, len)?;
+        // all leaves must be free before changing anything
+        let mut offset = start;
+        while offset < end {
+            let (k, q) = self
+                .free_block_containing(offset)
+                .ok_or(ReserveError::InUse)?;
+            offset = q - self.base_addr + block_size_2base(k, self.leaf2base);
+        }
+        let mut offset = start;
+        while offset < end {
+            let (k, q) = self.free_block_containing(offset).expect("free block");
+            self.remove_free(k, q as *mut u8);
+            bit_set(self.entry(k).alloc, self.block_index(k, q as *const u8));
+            self.carve(k, q, start, end);
+            offset = q - self.base_addr + block_size_2base(k, self.leaf2base);
+        }
+        Ok(())
+    }
+
+    /// Give back a range reserved by `reserve`, with the same addr and len
+    pub fn release_reserved(&mut self, addr: *const u8, len: usize) {
+        let (start, end) = self
+            .covering_leaves(addr, len)
+            .expect("release a range out of the heap");
+        let mut offset = start;
+        while offset < end {
+            let p = (self.base_addr + offset) as *mut u8;
+            let k = self.find_k_for_p(p);
+            offset += block_size_2base(k, self.leaf2base);
+            debug_assert!(offset <= end, "release a range not reserved");
+            self.free_block(p, k);
+        }
+    }
+
+    /// Returns offsets of the leaves covering the range
+    fn covering_leaves(&self, addr: *const u8, len: usize) -> Result<(usize, usize), ReserveError> {
+        let leaf_mask = (1 << self.leaf2base) - 1;
+        let offset = (addr as usize)
+            .checked_sub(self.base_addr)
+            .ok_or(ReserveError::OutOfHeap)?;
+        let end = offset
+            .checked_add(len)
+            .filter(|&end| end <= self.len)
+            .ok_or(ReserveError::OutOfHeap)?;
+        // leaves out of the available memory are allocated, so they are in use
+        Ok((offset & !leaf_mask, (end + leaf_mask) & !leaf_mask))
+    }
+
+    /// Returns the order and addr of the free block contains the leaf at offset
+    fn free_block_containing(&self, offset: usize) -> Option<(usize, usize)> {
+        let p = (self.base_addr + offset) as *const u8;
+        for k in 0..(self.entries_size - 1) {
+            let block_index = self.block_index(k, p);
+            if bit_isset(self.entry(k).alloc, block_index) {
+                // an allocated block, split blocks are found as the parents of free blocks
+                return None;
+            }
+            if bit_isset(self.entry(k + 1).split, self.block_index(k + 1, p)) {
+                return Some((k, self.block_addr(k, block_index)));
+            }
+        }
+        None
+    }
+
+    /// split the allocated block q of order k until its allocated parts are inside
+    /// `start..end` offsets, frees the others
+    fn carve(&mut self, k: usize, q: usize, start: usize, end: usize) {
+        let offset = q - self.base_addr;
+        let size = block_size_2base(k, self.leaf2base);
+        if offset >= start && end - offset >= size {
+            return;
+        }
+        let half = size >> 1;
+        bit_set(self.entry(k).split, self.block_index(k, q as *const u8));
+        for r in [q, q + half] {
+            let r_offset = r - self.base_addr;
+            if r_offset < end && r_offset + half > start {
+                bit_set(
+                    self.entry(k - 1).alloc,
+                    self.block_index(k - 1, r as *const u8),
+                );
+                self.carve(k - 1, r, start, end);
+            } else {
+                self.release(k - 1, r as *mut u8);
+            }
+        }
     }
 
     /// Returns the bytes currently available for allocation.
@@ -389,7 +1170,314 @@ impl BuddyAlloc {
     /// at once.
     /// https://github.com/jjyr/buddy-alloc/issues/7
     pub fn available_bytes(&self) -> usize {
-        self.end_addr - self.unavailable - self.base_addr
+        self.len - self.unavailable
+    }
+
+    /// Returns a snapshot of the usage, free lists are walked to collect it.
+    pub fn stats(&self) -> Stats {
+        let mut stats = Stats {
+            available_bytes: self.available_bytes(),
+            ..Stats::default()
+        };
+        // the top entry is a dummy
+        for k in 0..(self.entries_size - 1) {
+            let n = Node::len(self.entry(k).free);
+            if n > 0 {
+                let block_size = block_size_2base(k, self.leaf2base);
+                stats.free_bytes += n * block_size;
+                stats.free_blocks += n;
+                stats.largest_free_block = block_size;
+            }
+        }
+        stats
+    }
+
+    /// Verify the free lists and the bitmaps, returns the first inconsistency found.
+    /// Walks all free lists and split blocks, so it's cheap enough to call periodically.
+    pub fn check_invariants(&self) -> Result<(), HeapCorruption> {
+        let top = self.entries_size - 1;
+        let mut listed = [0; usize::BITS as usize];
+        let mut listed_bytes = 0;
+        for k in 0..top {
+            listed[k] = self.check_free_list(k)?;
+            if (self.nonempty >> k & 1 == 1) != (listed[k] > 0) {
+                return Err(HeapCorruption::NonemptyMask { order: k });
+            }
+            listed_bytes += listed[k] * block_size_2base(k, self.leaf2base);
+        }
+
+        // the dummy top order is always split
+        if !bit_isset(self.entry(top).alloc, 0) || !bit_isset(self.entry(top).split, 0) {
+            return Err(HeapCorruption::SplitNotAllocated {
+                order: top,
+                addr: self.base_addr,
+            });
+        }
+        let mut walk = TreeWalk {
+            free_blocks: [0; usize::BITS as usize],
+            free_bytes: 0,
+        };
+        self.check_block(top - 1, 0, &mut walk)?;
+        self.check_block(
+            top - 1,
+            block_size_2base(top - 1, self.leaf2base),
+            &mut walk,
+        )?;
+        if walk.free_bytes != listed_bytes {
+            return Err(HeapCorruption::ByteCountMismatch {
+                listed_bytes,
+                free_bytes: walk.free_bytes,
+            });
+        }
+        for k in 0..top {
+            if walk.free_blocks[k] != listed[k] {
+                return Err(HeapCorruption::FreeListMismatch { order: k });
+            }
+        }
+        Ok(())
+    }
+
+    /// check nodes of the free list of order k, returns the number of nodes
+    fn check_free_list(&self, k: usize) -> Result<usize, HeapCorruption> {
+        let list = self.entry(k).free;
+        let block_size = block_size_2base(k, self.leaf2base);
+        let mut n = 0;
+        let mut prev = list;
+        let mut node = unsafe { (*list).next };
+        while !core::ptr::eq(node, list) {
+            let addr = node as usize;
+            // the node must be checked before reading it
+            if !self.contains_ptr(node.cast()) || self.len - (addr - self.base_addr) < block_size {
+                return Err(HeapCorruption::NodeOutOfHeap { order: k, addr });
+            }
+            if (addr - self.base_addr) & (block_size - 1) != 0 {
+                return Err(HeapCorruption::MisalignedNode { order: k, addr });
+            }
+            // a list longer than the blocks of the order has a loop
+            if unsafe { !core::ptr::eq((*node).prev, prev) } || n >= nblock(k, self.entries_size) {
+                return Err(HeapCorruption::BrokenLink { order: k, addr });
+            }
+            let block_index = self.block_index(k, node.cast());
+            if bit_isset(self.entry(k).alloc, block_index) {
+                return Err(HeapCorruption::FreeBlockAllocated { order: k, addr });
+            }
+            if !bit_isset(self.entry(k + 1).split, block_index >> 1) {
+                return Err(HeapCorruption::ParentNotSplit { order: k, addr });
+            }
+            if !bit_isset(self.entry(k).alloc, block_index ^ 1) {
+                return Err(HeapCorruption::UnmergedBuddies { order: k, addr });
+            }
+            n += 1;
+            prev = node;
+            node = unsafe { (*node).next };
+        }
+        if unsafe { !core::ptr::eq((*list).prev, prev) } {
+            return Err(HeapCorruption::BrokenLink {
+                order: k,
+                addr: list as usize,
+            });
+        }
+        Ok(n)
+    }
+
+    /// check the order k block at offset and the blocks split from it
+    fn check_block(
+        &self,
+        k: usize,
+        offset: usize,
+        walk: &mut TreeWalk,
+    ) -> Result<(), HeapCorruption> {
+        let block_size = block_size_2base(k, self.leaf2base);
+        // equal to: block_index(k, base_addr + offset), which may overflow
+        let block_index = (offset >> k) >> self.leaf2base;
+        let addr = self.base_addr.wrapping_add(offset);
+        let alloc = bit_isset(self.entry(k).alloc, block_index);
+        if k > 0 && bit_isset(self.entry(k).split, block_index) {
+            if !alloc {
+                return Err(HeapCorruption::SplitNotAllocated { order: k, addr });
+            }
+            self.check_block(k - 1, offset, walk)?;
+            return self.check_block(k - 1, offset + (block_size >> 1), walk);
+        }
+        if k > 0 {
+            let child_entry = self.entry(k - 1);
+            let stale =
+                |i| bit_isset(child_entry.alloc, i) || (k > 1 && bit_isset(child_entry.split, i));
+            if stale(block_index << 1) || stale((block_index << 1) + 1) {
+                return Err(HeapCorruption::StaleChildBits { order: k, addr });
+            }
+        }
+        if !alloc {
+            // blocks out of the heap are counted as empty
+            walk.free_blocks[k] += 1;
+            walk.free_bytes += core::cmp::min(block_size, self.len.saturating_sub(offset));
+        }
+        Ok(())
+    }
+
+    /// Flip the alloc bit of the order k block which contains p, to corrupt the heap in tests
+    #[cfg(test)]
+    pub(crate) fn toggle_alloc_bit(&mut self, k: usize, p: *const u8) {
+        let block_index = self.block_index(k, p);
+        let alloc = self.entry(k).alloc;
+        if bit_isset(alloc, block_index) {
+            bit_clear(alloc, block_index);
+        } else {
+            bit_set(alloc, block_index);
+        }
+    }
+
+    /// Flip the split bit of the order k block which contains p, to corrupt the heap in tests
+    #[cfg(test)]
+    pub(crate) fn toggle_split_bit(&mut self, k: usize, p: *const u8) {
+        let block_index = self.block_index(k, p);
+        let split = self.entry(k).split;
+        if bit_isset(split, block_index) {
+            bit_clear(split, block_index);
+        } else {
+            bit_set(split, block_index);
+        }
+    }
+
+    /// Render the heap as a text map, one character per `granularity` bytes and
+    /// `MAP_COLUMNS` characters per line, each line starts with the address of its first cell.
+    /// See `MAP_FREE`, `MAP_ALLOCATED`, `MAP_SPLIT` and `MAP_METADATA` for the characters.
+    /// The granularity must be a power of two and not smaller than the leaf size.
+    pub fn write_map<W: core::fmt::Write>(
+        &self,
+        w: &mut W,
+        granularity: usize,
+    ) -> core::fmt::Result {
+        assert!(
+            granularity.is_power_of_two() && granularity >> self.leaf2base > 0,
+            "granularity must be a power of two and not smaller than the leaf size"
+        );
+        let metadata = self.metadata();
+        // cells before the heap are only shown for the metadata in front of it
+        let before = self.base_addr.saturating_sub(metadata.start);
+        let before = (before + granularity - 1) & !(granularity - 1);
+        let start = self.base_addr - before;
+        let cells = (before + self.len).div_ceil(granularity);
+        for i in 0..cells {
+            let addr = start + i * granularity;
+            if i % MAP_COLUMNS == 0 {
+                if i > 0 {
+                    w.write_char('\n')?;
+                }
+                write!(w, "{:#x} ", addr)?;
+            }
+            let cell = if addr < self.base_addr
+                || (addr < metadata.end && metadata.start.saturating_sub(addr) < granularity)
+            {
+                MAP_METADATA
+            } else {
+                self.map_cell(addr - self.base_addr, granularity)
+            };
+            w.write_char(cell)?;
+        }
+        w.write_char('\n')
+    }
+
+    /// Returns the character of the `granularity` bytes cell at offset
+    fn map_cell(&self, offset: usize, granularity: usize) -> char {
+        let j = log2(granularity >> self.leaf2base);
+        // walks down from the dummy top order, which is always split
+        let mut k = self.entries_size - 1;
+        let mut block_index = 0;
+        while k > j && bit_isset(self.entry(k).split, block_index) {
+            k -= 1;
+            block_index = (offset >> k) >> self.leaf2base;
+        }
+        if k > 0 && k == j && bit_isset(self.entry(k).split, block_index) {
+            MAP_SPLIT
+        } else if bit_isset(self.entry(k).alloc, block_index) {
+            MAP_ALLOCATED
+        } else {
+            MAP_FREE
+        }
+    }
+
+    /// Write a JSON object per line for the metadata and each block which is not split,
+    /// e.g. `{"addr":4096,"order":2,"size":64,"state":"free"}`
+    #[cfg(feature = "json-map")]
+    pub fn write_json_map<W: core::fmt::Write>(&self, w: &mut W) -> core::fmt::Result {
+        let metadata = self.metadata();
+        writeln!(
+            w,
+            r#"{{"addr":{},"size":{},"state":"metadata"}}"#,
+            metadata.start,
+            metadata.end - metadata.start
+        )?;
+        let top = self.entries_size - 1;
+        self.write_json_block(w, top - 1, 0)?;
+        self.write_json_block(w, top - 1, block_size_2base(top - 1, self.leaf2base))
+    }
+
+    #[cfg(feature = "json-map")]
+    fn write_json_block<W: core::fmt::Write>(
+        &self,
+        w: &mut W,
+        k: usize,
+        offset: usize,
+    ) -> core::fmt::Result {
+        if offset >= self.len {
+            return Ok(());
+        }
+        let block_size = block_size_2base(k, self.leaf2base);
+        let block_index = (offset >> k) >> self.leaf2base;
+        if k > 0 && bit_isset(self.entry(k).split, block_index) {
+            self.write_json_block(w, k - 1, offset)?;
+            return self.write_json_block(w, k - 1, offset + (block_size >> 1));
+        }
+        let state = if bit_isset(self.entry(k).alloc, block_index) {
+            "allocated"
+        } else {
+            "free"
+        };
+        writeln!(
+            w,
+            r#"{{"addr":{},"order":{},"size":{},"state":"{}"}}"#,
+            self.base_addr + offset,
+            k,
+            block_size,
+            state
+        )
+    }
+
+    /// Returns the address range of the metadata
+    fn metadata(&self) -> Range<usize> {
+        let start = self.entries as usize;
+        start..(start + metadata_size(self.entries_size, !self.extent.is_null()))
+    }
+
+    /// Returns true if p is inside the memory managed by this allocator
+    pub fn contains_ptr(&self, p: *const u8) -> bool {
+        let addr = p as usize;
+        addr >= self.base_addr && addr - self.base_addr < self.len
+    }
+
+    /// Returns the start of the allocated `block_size` bytes block which contains p,
+    /// returns None if there is no such block or p is the start of it.
+    pub(crate) fn enclosing_block(&self, p: *const u8, block_size: usize) -> Option<*mut u8> {
+        if !self.contains_ptr(p) {
+            return None;
+        }
+        let k = first_up_k(block_size, 1 << self.leaf2base);
+        debug_assert_eq!(block_size_2base(k, self.leaf2base), block_size);
+        if k >= self.entries_size - 1 {
+            return None;
+        }
+        let block_index = self.block_index(k, p);
+        let block_addr = self.block_addr(k, block_index);
+        let entry = self.entry(k);
+        // leaves have no split bits
+        if block_addr == p as usize
+            || !bit_isset(entry.alloc, block_index)
+            || (k > 0 && bit_isset(entry.split, block_index))
+        {
+            return None;
+        }
+        Some(block_addr as *mut u8)
     }
 
     fn entry(&self, i: usize) -> &Entry {
@@ -397,15 +1485,70 @@ impl BuddyAlloc {
         unsafe { self.entries.add(i).as_ref().expect("entry") }
     }
 
+    /// all free list operations go through these helpers, which maintain the non-empty mask
+    fn push_free(&mut self, k: usize, p: *mut u8) {
+        self.push_node(self.entry(k).free, p);
+        self.nonempty |= 1 << k;
+    }
+
+    /// push p to the list, in the order of the placement policy
+    fn push_node(&self, list: *mut Node, p: *mut u8) {
+        match self.placement {
+            Placement::Lifo => Node::push(list, p),
+            Placement::LowestAddress => Node::push_ordered(list, p, true),
+            Placement::HighestAddress => Node::push_ordered(list, p, false),
+        }
+    }
+
+    fn pop_free(&mut self, k: usize) -> *mut u8 {
+        let list = self.entry(k).free;
+        let p = Node::pop(list);
+        if Node::is_empty(list) {
+            self.nonempty &= !(1 << k);
+        }
+        p.cast()
+    }
+
+    fn remove_free(&mut self, k: usize, p: *mut u8) {
+        Node::remove(p.cast());
+        if Node::is_empty(self.entry(k).free) {
+            self.nonempty &= !(1 << k);
+        }
+    }
+
+    /// record clean state of a free block, the state is stored right after the node header.
+    /// blocks too small to hold the state are always dirty.
+    fn set_clean(&self, k: usize, p: *mut u8, clean: bool) {
+        if self.track_clean && block_size_2base(k, self.leaf2base) >= CLEAN_HEADER_SIZE {
+            let mark = if clean { CLEAN_MARK } else { 0 };
+            unsafe { p.add(NODE_SIZE).cast::<usize>().write(mark) };
+        }
+    }
+
+    /// whether a free block is zero filled except the clean header
+    fn is_clean(&self, k: usize, p: *mut u8) -> bool {
+        self.track_clean
+            && block_size_2base(k, self.leaf2base) >= CLEAN_HEADER_SIZE
+            && unsafe { p.add(NODE_SIZE).cast::<usize>().read() } == CLEAN_MARK
+    }
+
     /// find k for p
+    /// blocks containing p are split above k and not split up to k,
+    /// so binary search the lowest split order j, then k = j - 1.
     fn find_k_for_p(&self, p: *const u8) -> usize {
-        for k in 0..(self.entries_size - 1) {
-            if bit_isset(self.entry(k + 1).split, self.block_index(k + 1, p)) {
-                debug_assert!(bit_isset(self.entry(k).alloc, self.block_index(k, p)));
-                return k;
+        // the dummy top order is always split
+        let (mut lo, mut hi) = (1, self.entries_size - 1);
+        while lo < hi {
+            let mid = (lo + hi) / 2;
+            if bit_isset(self.entry(mid).split, self.block_index(mid, p)) {
+                hi = mid;
+            } else {
+                lo = mid + 1;
             }
         }
-        0
+        let k = lo - 1;
+        debug_assert!(bit_isset(self.entry(k).alloc, self.block_index(k, p)));
+        k
     }
 
     /// block index of p under k
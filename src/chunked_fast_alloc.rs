@@ -0,0 +1,211 @@
+//! Chunked fast allocator
+//! A fast allocator without a dedicated region,
+//! it carves chunks from BuddyAlloc on demand and gives a chunk back once all its blocks are free.
+
+use crate::buddy_alloc::BuddyAlloc;
+use crate::fast_alloc::BLOCK_SIZE;
+use crate::poison::Poison;
+use crate::zeroize::zeroize;
+
+// By default, carve 4 KB chunks from BuddyAlloc
+pub const DEFAULT_CHUNK_SIZE: usize = 4096;
+
+/// The header of a chunk, placed at the first block of the chunk
+struct Chunk {
+    /// links of the chunks that have free blocks
+    next: *mut Chunk,
+    prev: *mut Chunk,
+    /// freed blocks
+    free: *mut FreeBlock,
+    /// offset from the chunk to allocate blocks
+    next_offset: usize,
+    /// allocated blocks
+    used: usize,
+}
+
+struct FreeBlock {
+    next: *mut FreeBlock,
+}
+
+const FREE_BLOCK_SIZE: usize = core::mem::size_of::<FreeBlock>();
+const HEADER_BLOCKS: usize = core::mem::size_of::<Chunk>().div_ceil(BLOCK_SIZE);
+
+#[derive(Clone, Copy)]
+pub struct ChunkedFastAllocParam {
+    chunk_size: usize,
+    poison: Option<Poison>,
+    zeroize: bool,
+}
+
+impl ChunkedFastAllocParam {
+    /// Chunk size: bytes carved from BuddyAlloc at once,
+    /// must be a power of two larger than `BLOCK_SIZE` and not smaller than the leaf size of BuddyAlloc.
+    pub const fn new(chunk_size: usize) -> Self {
+        ChunkedFastAllocParam {
+            chunk_size,
+            poison: None,
+            zeroize: false,
+        }
+    }
+
+    /// Debug mode: poison blocks on alloc and free, see `Poison`
+    pub const fn with_poison(mut self, poison: Poison) -> Self {
+        self.poison = Some(poison);
+        self
+    }
+
+    /// Secure mode: wipe every freed block, so the freed memory does not keep secrets.
+    pub const fn with_zeroize(mut self) -> Self {
+        self.zeroize = true;
+        self
+    }
+}
+
+impl Default for ChunkedFastAllocParam {
+    fn default() -> Self {
+        Self::new(DEFAULT_CHUNK_SIZE)
+    }
+}
+
+pub struct ChunkedFastAlloc {
+    chunk_size: usize,
+    /// chunks that have free blocks
+    partial: *mut Chunk,
+    poison: Option<Poison>,
+    zeroize: bool,
+}
+
+impl ChunkedFastAlloc {
+    pub fn new(param: ChunkedFastAllocParam) -> Self {
+        let ChunkedFastAllocParam {
+            chunk_size,
+            poison,
+            zeroize,
+        } = param;
+        assert!(
+            chunk_size.is_power_of_two() && chunk_size > BLOCK_SIZE * HEADER_BLOCKS,
+            "chunk size must be a power of two and larger than the block size"
+        );
+        ChunkedFastAlloc {
+            chunk_size,
+            partial: core::ptr::null_mut(),
+            poison,
+            zeroize,
+        }
+    }
+
+    fn capacity(&self) -> usize {
+        self.chunk_size / BLOCK_SIZE - HEADER_BLOCKS
+    }
+
+    /// Returns true if p is a block of a chunk carved from `buddy`
+    pub fn contains_ptr(&self, p: *mut u8, buddy: &BuddyAlloc) -> bool {
+        buddy.enclosing_block(p, self.chunk_size).is_some()
+    }
+
+    pub fn malloc(&mut self, nbytes: usize, buddy: &mut BuddyAlloc) -> *mut u8 {
+        if nbytes > BLOCK_SIZE {
+            return core::ptr::null_mut();
+        }
+        if self.partial.is_null() && !self.refill(buddy) {
+            return core::ptr::null_mut();
+        }
+        let chunk = self.partial;
+        let p = unsafe {
+            let p = if !(*chunk).free.is_null() {
+                let p = (*chunk).free;
+                (*chunk).free = (*p).next;
+                if let Some(poison) = self.poison {
+                    poison.check_freed(p as *mut u8, FREE_BLOCK_SIZE, BLOCK_SIZE);
+                }
+                p as *mut u8
+            } else {
+                let p = chunk.cast::<u8>().add((*chunk).next_offset);
+                (*chunk).next_offset += BLOCK_SIZE;
+                p
+            };
+            (*chunk).used += 1;
+            if (*chunk).used == self.capacity() {
+                self.unlink(chunk);
+            }
+            p
+        };
+        if let Some(poison) = self.poison {
+            poison.fill_alloc(p, BLOCK_SIZE);
+        }
+        p
+    }
+
+    pub fn free(&mut self, p: *mut u8, buddy: &mut BuddyAlloc) {
+        let chunk = buddy
+            .enclosing_block(p, self.chunk_size)
+            .expect("free a block not belongs to chunks")
+            .cast::<Chunk>();
+        if self.zeroize {
+            zeroize(p, BLOCK_SIZE);
+        }
+        if let Some(poison) = self.poison {
+            poison.fill_free(p, BLOCK_SIZE);
+        }
+        unsafe {
+            if (*chunk).used == self.capacity() {
+                self.link(chunk);
+            }
+            let block = p.cast::<FreeBlock>();
+            block.write(FreeBlock {
+                next: (*chunk).free,
+            });
+            (*chunk).free = block;
+            (*chunk).used -= 1;
+            if (*chunk).used == 0 {
+                // all blocks are free, give the chunk back
+                self.unlink(chunk);
+                buddy.free(chunk.cast());
+            }
+        }
+    }
+
+    /// carve a new chunk from buddy
+    fn refill(&mut self, buddy: &mut BuddyAlloc) -> bool {
+        let chunk = buddy.malloc(self.chunk_size).cast::<Chunk>();
+        if chunk.is_null() {
+            return false;
+        }
+        unsafe {
+            chunk.write(Chunk {
+                next: core::ptr::null_mut(),
+                prev: core::ptr::null_mut(),
+                free: core::ptr::null_mut(),
+                next_offset: HEADER_BLOCKS * BLOCK_SIZE,
+                used: 0,
+            });
+        }
+        self.link(chunk);
+        true
+    }
+
+    fn link(&mut self, chunk: *mut Chunk) {
+        unsafe {
+            (*chunk).prev = core::ptr::null_mut();
+            (*chunk).next = self.partial;
+            if !self.partial.is_null() {
+                (*self.partial).prev = chunk;
+            }
+        }
+        self.partial = chunk;
+    }
+
+    fn unlink(&mut self, chunk: *mut Chunk) {
+        unsafe {
+            let Chunk { next, prev, .. } = chunk.read();
+            if prev.is_null() {
+                self.partial = next;
+            } else {
+                (*prev).next = next;
+            }
+            if !next.is_null() {
+                (*next).prev = prev;
+            }
+        }
+    }
+}
@@ -0,0 +1,98 @@
+//! Heap corruption
+//! Inconsistencies found by the `check_invariants` of the allocators.
+
+/// The first inconsistency found, addrs are the starts of the blocks.
+/// Blocks of `FastAlloc` are reported as order 0.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HeapCorruption {
+    /// The links of a free list node don't point back to it, or the list has a loop
+    BrokenLink { order: usize, addr: usize },
+    /// A free list node is out of the heap
+    NodeOutOfHeap { order: usize, addr: usize },
+    /// A free list node is not aligned to the block size of its order
+    MisalignedNode { order: usize, addr: usize },
+    /// A block on a free list is marked allocated
+    FreeBlockAllocated { order: usize, addr: usize },
+    /// The parent of a free block is not marked split
+    ParentNotSplit { order: usize, addr: usize },
+    /// A free block and its buddy are both free, they should be merged
+    UnmergedBuddies { order: usize, addr: usize },
+    /// The non-empty orders mask does not match the free list of the order
+    NonemptyMask { order: usize },
+    /// A block is marked split, but not allocated
+    SplitNotAllocated { order: usize, addr: usize },
+    /// A block is not split, but its children have alloc or split bits
+    StaleChildBits { order: usize, addr: usize },
+    /// The free blocks found in the tree are not the blocks on the free list of the order
+    FreeListMismatch { order: usize },
+    /// The bytes of the free lists don't add up to the free blocks found in the tree
+    ByteCountMismatch {
+        listed_bytes: usize,
+        free_bytes: usize,
+    },
+}
+
+impl core::fmt::Display for HeapCorruption {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match *self {
+            HeapCorruption::BrokenLink { order, addr } => {
+                write!(f, "broken free list link at {:#x} (order {})", addr, order)
+            }
+            HeapCorruption::NodeOutOfHeap { order, addr } => {
+                write!(
+                    f,
+                    "free block {:#x} out of the heap (order {})",
+                    addr, order
+                )
+            }
+            HeapCorruption::MisalignedNode { order, addr } => {
+                write!(f, "misaligned free block {:#x} (order {})", addr, order)
+            }
+            HeapCorruption::FreeBlockAllocated { order, addr } => {
+                write!(f, "free block {:#x} is allocated (order {})", addr, order)
+            }
+            HeapCorruption::ParentNotSplit { order, addr } => {
+                write!(
+                    f,
+                    "parent of free block {:#x} is not split (order {})",
+                    addr, order
+                )
+            }
+            HeapCorruption::UnmergedBuddies { order, addr } => {
+                write!(
+                    f,
+                    "buddy of free block {:#x} is free (order {})",
+                    addr, order
+                )
+            }
+            HeapCorruption::NonemptyMask { order } => {
+                write!(f, "non-empty mask mismatch (order {})", order)
+            }
+            HeapCorruption::SplitNotAllocated { order, addr } => {
+                write!(
+                    f,
+                    "split block {:#x} is not allocated (order {})",
+                    addr, order
+                )
+            }
+            HeapCorruption::StaleChildBits { order, addr } => {
+                write!(
+                    f,
+                    "children of block {:#x} have stale bits (order {})",
+                    addr, order
+                )
+            }
+            HeapCorruption::FreeListMismatch { order } => {
+                write!(f, "free list mismatch (order {})", order)
+            }
+            HeapCorruption::ByteCountMismatch {
+                listed_bytes,
+                free_bytes,
+            } => write!(
+                f,
+                "{} bytes on the free lists, but {} free bytes in the tree",
+                listed_bytes, free_bytes
+            ),
+        }
+    }
+}
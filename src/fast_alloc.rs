@@ -1,8 +1,12 @@
 //! Fast allocator
 //! Optimized for fixed small memory block.
 
-// Fix size 64 Bytes
-pub const BLOCK_SIZE: usize = 64;
+use crate::corruption::HeapCorruption;
+use crate::poison::Poison;
+use crate::zeroize::zeroize;
+
+// Default block size: four Nodes, 64 Bytes on 64-bits machine and 32 Bytes on 32-bits
+pub const BLOCK_SIZE: usize = 4 * NODE_SIZE;
 
 // By default, initialize 4 nodes at most
 pub const DEFAULT_INITIALIZED_NODES: usize = 4;
@@ -12,6 +16,8 @@ struct Node {
     prev: *mut Node,
 }
 
+const NODE_SIZE: usize = core::mem::size_of::<Node>();
+
 impl Node {
     fn init(list: *mut Node) {
         unsafe {
@@ -53,7 +59,7 @@ impl Node {
     }
 
     fn is_empty(list: *const Node) -> bool {
-        unsafe { (*list).next as *const Node == list }
+        unsafe { core::ptr::eq((*list).next, list) }
     }
 }
 
@@ -62,6 +68,8 @@ pub struct FastAllocParam {
     base_addr: *const u8,
     len: usize,
     initialized_nodes: usize,
+    poison: Option<Poison>,
+    zeroize: bool,
 }
 
 impl FastAllocParam {
@@ -70,9 +78,13 @@ impl FastAllocParam {
             base_addr,
             len,
             initialized_nodes: DEFAULT_INITIALIZED_NODES,
+            poison: None,
+            zeroize: false,
         }
     }
 
+    /// Initialized nodes: blocks pushed to the free list by `new`,
+    /// with 0 blocks are only written once they are allocated.
     pub const fn new_with_initialized_nodes(
         base_addr: *const u8,
         len: usize,
@@ -82,21 +94,48 @@ impl FastAllocParam {
             base_addr,
             len,
             initialized_nodes,
+            poison: None,
+            zeroize: false,
         }
     }
+
+    /// Debug mode: poison blocks on alloc and free, see `Poison`
+    pub const fn with_poison(mut self, poison: Poison) -> Self {
+        self.poison = Some(poison);
+        self
+    }
+
+    /// Secure mode: wipe every freed block, so the freed memory does not keep secrets.
+    pub const fn with_zeroize(mut self) -> Self {
+        self.zeroize = true;
+        self
+    }
 }
 
-pub struct FastAlloc {
+/// Fast allocator with the default `BLOCK_SIZE`
+pub type FastAlloc = GenericFastAlloc<BLOCK_SIZE>;
+
+/// Fast allocator serves fixed `BLOCK` bytes blocks,
+/// `BLOCK` must be a power of two and large enough to hold a free list node.
+pub struct GenericFastAlloc<const BLOCK: usize> {
     /// memory start addr
     base_addr: usize,
-    /// memory end addr
-    end_addr: usize,
-    /// next addr to allocate nodes
-    next_addr: usize,
+    /// memory bytes from base_addr
+    len: usize,
+    /// offset from base_addr to allocate nodes
+    next_offset: usize,
     free: *mut Node,
+    poison: Option<Poison>,
+    zeroize: bool,
 }
 
-impl FastAlloc {
+impl<const BLOCK: usize> GenericFastAlloc<BLOCK> {
+    /// Compile time check of the block size
+    const BLOCK_CHECK: () = assert!(
+        BLOCK >= NODE_SIZE && BLOCK.is_power_of_two(),
+        "block size must be a power of two and can hold a node"
+    );
+
     /// # Safety
     ///
     /// The `base_addr..(base_addr + len)` must be allocated before using,
@@ -106,55 +145,114 @@ impl FastAlloc {
             base_addr,
             len,
             initialized_nodes,
+            poison,
+            zeroize,
         } = param;
-        let nblocks = len / BLOCK_SIZE;
-        debug_assert_eq!(len % BLOCK_SIZE, 0);
+        let () = Self::BLOCK_CHECK;
+        let nblocks = len / BLOCK;
+        debug_assert_eq!(len % BLOCK, 0);
 
         let base_addr = base_addr as usize;
-        let end_addr = base_addr + nblocks * BLOCK_SIZE;
-
-        debug_assert_eq!(
-            base_addr % BLOCK_SIZE,
-            0,
-            "base_addr must align to block size"
+        let len = nblocks * BLOCK;
+        // `base_addr + len` may be the top of the address space
+        assert!(
+            len == 0 || base_addr.checked_add(len - 1).is_some(),
+            "memory range overflows the address space"
         );
 
+        debug_assert_eq!(base_addr % BLOCK, 0, "base_addr must align to block size");
+
         // Actual blocks to create here
         let cblocks = core::cmp::min(nblocks, initialized_nodes);
 
-        // initialize free list
-        let free = base_addr as *mut Node;
-        Node::init(free);
+        // the blocks after the initialized nodes are poisoned once they are allocated
+        if let Some(poison) = poison {
+            poison.fill_free(base_addr as *mut u8, cblocks * BLOCK);
+        }
 
-        let mut addr = base_addr;
-        for _ in 1..cblocks {
-            addr += BLOCK_SIZE;
-            Node::push(free, addr as *mut u8);
+        // initialize free list, the memory is not touched without initialized nodes
+        let free = if cblocks == 0 {
+            core::ptr::null_mut()
+        } else {
+            let free = base_addr as *mut Node;
+            Node::init(free);
+            free
+        };
+
+        for i in 1..cblocks {
+            Node::push(free, (base_addr + i * BLOCK) as *mut u8);
         }
 
-        FastAlloc {
+        GenericFastAlloc {
             base_addr,
-            end_addr,
-            next_addr: addr + BLOCK_SIZE,
+            len,
+            next_offset: cblocks * BLOCK,
             free,
+            poison,
+            zeroize,
         }
     }
 
     pub fn contains_ptr(&self, p: *mut u8) -> bool {
         let addr = p as usize;
-        addr >= self.base_addr && addr < self.end_addr
+        addr >= self.base_addr && addr - self.base_addr < self.len
+    }
+
+    /// Returns the size of each block
+    pub const fn block_size(&self) -> usize {
+        BLOCK
+    }
+
+    /// Verify the links of the free list, returns the first inconsistency found
+    pub fn check_invariants(&self) -> Result<(), HeapCorruption> {
+        if self.free.is_null() {
+            return Ok(());
+        }
+        // only blocks below next_offset are ever freed
+        let in_heap = |node: *mut Node| {
+            let addr = node as usize;
+            addr >= self.base_addr && addr - self.base_addr < self.next_offset
+        };
+        let mut n = 0;
+        let mut node = self.free;
+        loop {
+            let addr = node as usize;
+            // the node must be checked before reading it
+            if !in_heap(node) {
+                return Err(HeapCorruption::NodeOutOfHeap { order: 0, addr });
+            }
+            if (addr - self.base_addr) & (BLOCK - 1) != 0 {
+                return Err(HeapCorruption::MisalignedNode { order: 0, addr });
+            }
+            let next = unsafe { (*node).next };
+            // a list longer than the blocks has a loop
+            n += 1;
+            if !in_heap(next)
+                || unsafe { !core::ptr::eq((*next).prev, node) }
+                || n > self.next_offset / BLOCK
+            {
+                return Err(HeapCorruption::BrokenLink { order: 0, addr });
+            }
+            node = next;
+            if core::ptr::eq(node, self.free) {
+                return Ok(());
+            }
+        }
     }
 
     pub fn malloc(&mut self, nbytes: usize) -> *mut u8 {
-        if nbytes > BLOCK_SIZE {
+        if nbytes > BLOCK {
             return core::ptr::null_mut();
         }
 
         if self.free.is_null() {
-            if self.next_addr < self.end_addr {
-                let result = self.next_addr;
-                self.next_addr += BLOCK_SIZE;
-                return result as *mut u8;
+            if self.next_offset < self.len {
+                let result = (self.base_addr + self.next_offset) as *mut u8;
+                self.next_offset += BLOCK;
+                if let Some(poison) = self.poison {
+                    poison.fill_alloc(result, BLOCK);
+                }
+                return result;
             } else {
                 return core::ptr::null_mut();
             }
@@ -165,11 +263,21 @@ impl FastAlloc {
         if is_last {
             self.free = core::ptr::null_mut();
         }
+        if let Some(poison) = self.poison {
+            poison.check_freed(p, NODE_SIZE, BLOCK);
+            poison.fill_alloc(p, BLOCK);
+        }
         p
     }
 
     pub fn free(&mut self, p: *mut u8) {
         debug_assert!(self.contains_ptr(p));
+        if self.zeroize {
+            zeroize(p, BLOCK);
+        }
+        if let Some(poison) = self.poison {
+            poison.fill_free(p, BLOCK);
+        }
         if self.free.is_null() {
             let n = p.cast();
             Node::init(n);
@@ -0,0 +1,211 @@
+//! Handle alloc
+//! Movable allocations on top of a buddy heap. Blocks are referred to by handles instead of
+//! pointers, so `compact` can move them together and let the buddies merge again.
+
+use crate::buddy_alloc::{BuddyAlloc, BuddyAllocParam, Placement, Stats};
+use core::cell::Cell;
+use core::ops::{Deref, DerefMut};
+
+const OOM_MSG: &str = "requires more memory space to initialize HandleAlloc";
+const INVALID_HANDLE_MSG: &str = "invalid handle";
+
+/// Refers to a block of a `HandleAlloc`, valid until the block is freed
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Handle(usize);
+
+struct Slot {
+    /// null if the slot is unused
+    ptr: *mut u8,
+    /// requested bytes, which are moved by compaction
+    size: usize,
+    /// pinned blocks are never moved
+    pins: usize,
+    locked: Cell<bool>,
+}
+
+#[derive(Clone, Copy)]
+pub struct HandleAllocParam {
+    buddy_alloc_param: BuddyAllocParam,
+    max_handles: usize,
+}
+
+impl HandleAllocParam {
+    /// Buddy alloc param: the heap, its placement is replaced by `Placement::LowestAddress`
+    /// Max handles: max number of live blocks, the handle table is allocated from the heap
+    pub const fn new(buddy_alloc_param: BuddyAllocParam, max_handles: usize) -> Self {
+        HandleAllocParam {
+            buddy_alloc_param,
+            max_handles,
+        }
+    }
+}
+
+/// Exclusive access to a block, the block can't be moved while it's locked
+pub struct Lock<'a> {
+    slot: &'a Slot,
+}
+
+impl Deref for Lock<'_> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        unsafe { core::slice::from_raw_parts(self.slot.ptr, self.slot.size) }
+    }
+}
+
+impl DerefMut for Lock<'_> {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        // only one lock of the block exists
+        unsafe { core::slice::from_raw_parts_mut(self.slot.ptr, self.slot.size) }
+    }
+}
+
+impl Drop for Lock<'_> {
+    fn drop(&mut self) {
+        self.slot.locked.set(false);
+    }
+}
+
+pub struct HandleAlloc {
+    inner: BuddyAlloc,
+    slots: *mut Slot,
+    max_handles: usize,
+}
+
+impl HandleAlloc {
+    /// # Safety
+    ///
+    /// See `BuddyAlloc::new`.
+    /// The new function panic if memory space not enough for the handle table.
+    pub unsafe fn new(param: HandleAllocParam) -> Self {
+        // blocks are moved to the lowest free addresses, leaving large blocks at the top
+        let buddy_alloc_param = param
+            .buddy_alloc_param
+            .with_placement(Placement::LowestAddress);
+        let mut inner = BuddyAlloc::new(buddy_alloc_param);
+        let table_size = param
+            .max_handles
+            .checked_mul(core::mem::size_of::<Slot>())
+            .expect(OOM_MSG);
+        let slots = inner.malloc(table_size).cast::<Slot>();
+        assert!(!slots.is_null(), "{}", OOM_MSG);
+        for i in 0..param.max_handles {
+            slots.add(i).write(Slot {
+                ptr: core::ptr::null_mut(),
+                size: 0,
+                pins: 0,
+                locked: Cell::new(false),
+            });
+        }
+        HandleAlloc {
+            inner,
+            slots,
+            max_handles: param.max_handles,
+        }
+    }
+
+    /// Returns None if the heap or the handle table is exhausted
+    pub fn malloc(&mut self, nbytes: usize) -> Option<Handle> {
+        let i = (0..self.max_handles).find(|&i| self.slot_at(i).ptr.is_null())?;
+        let p = self.inner.malloc(nbytes);
+        if p.is_null() {
+            return None;
+        }
+        let slot = self.slot_mut_at(i);
+        slot.ptr = p;
+        slot.size = nbytes;
+        Some(Handle(i))
+    }
+
+    /// Panics if the block is pinned
+    pub fn free(&mut self, handle: Handle) {
+        let slot = self.slot_mut(handle);
+        assert_eq!(slot.pins, 0, "free a pinned block");
+        let p = core::mem::replace(&mut slot.ptr, core::ptr::null_mut());
+        self.inner.free(p);
+    }
+
+    /// Returns the requested bytes of the block
+    pub fn size(&self, handle: Handle) -> usize {
+        self.slot(handle).size
+    }
+
+    /// Locks the block for access, returns None if it's already locked
+    pub fn lock(&self, handle: Handle) -> Option<Lock<'_>> {
+        let slot = self.slot(handle);
+        if slot.locked.replace(true) {
+            return None;
+        }
+        Some(Lock { slot })
+    }
+
+    /// Pins the block, the returned pointer is valid until the block is unpinned.
+    /// Pins are counted, the block is movable again after the same number of `unpin`.
+    pub fn pin(&mut self, handle: Handle) -> *mut u8 {
+        let slot = self.slot_mut(handle);
+        slot.pins += 1;
+        slot.ptr
+    }
+
+    pub fn unpin(&mut self, handle: Handle) {
+        let slot = self.slot_mut(handle);
+        assert!(slot.pins > 0, "unpin a block not pinned");
+        slot.pins -= 1;
+    }
+
+    /// Moves unpinned blocks to lower addresses, so their old blocks merge with the free buddies.
+    /// Returns the number of moved blocks.
+    pub fn compact(&mut self) -> usize {
+        let mut moved = 0;
+        // every move lowers the address of a block, so the passes end
+        loop {
+            let mut moved_in_pass = 0;
+            for i in 0..self.max_handles {
+                let slot = self.slot_at(i);
+                let (p, size, pins) = (slot.ptr, slot.size, slot.pins);
+                if p.is_null() || pins > 0 {
+                    continue;
+                }
+                let q = self.inner.malloc_below(size, p);
+                if q.is_null() {
+                    continue;
+                }
+                unsafe { core::ptr::copy_nonoverlapping(p, q, size) };
+                self.inner.free(p);
+                self.slot_mut_at(i).ptr = q;
+                moved_in_pass += 1;
+            }
+            if moved_in_pass == 0 {
+                return moved;
+            }
+            moved += moved_in_pass;
+        }
+    }
+
+    /// Returns a snapshot of the usage of the heap, the handle table is counted as allocated
+    pub fn stats(&self) -> Stats {
+        self.inner.stats()
+    }
+
+    fn slot_at(&self, i: usize) -> &Slot {
+        debug_assert!(i < self.max_handles, "index out of range");
+        unsafe { &*self.slots.add(i) }
+    }
+
+    fn slot_mut_at(&mut self, i: usize) -> &mut Slot {
+        debug_assert!(i < self.max_handles, "index out of range");
+        unsafe { &mut *self.slots.add(i) }
+    }
+
+    fn slot(&self, handle: Handle) -> &Slot {
+        assert!(handle.0 < self.max_handles, "{}", INVALID_HANDLE_MSG);
+        let slot = self.slot_at(handle.0);
+        assert!(!slot.ptr.is_null(), "{}", INVALID_HANDLE_MSG);
+        slot
+    }
+
+    fn slot_mut(&mut self, handle: Handle) -> &mut Slot {
+        self.slot(handle);
+        self.slot_mut_at(handle.0)
+    }
+}
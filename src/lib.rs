@@ -1,11 +1,37 @@
+//! Buddy-alloc is a memory allocator for no-std Rust.
+//!
+//! Diagnostics panic on failure: the `OomPolicy::Panic` policy, redzone checks and
+//! use-after-free checks of poisoning. Unwinding out of a global allocator is undefined
+//! behavior, so build with `panic = "abort"` when any of them is enabled.
+
 #![cfg_attr(not(test), no_std)]
 
 pub mod buddy_alloc;
+pub mod chunked_fast_alloc;
+pub mod corruption;
 pub mod fast_alloc;
+pub mod handle_alloc;
 pub mod non_threadsafe_alloc;
+pub mod oom;
+pub mod poison;
+pub mod raw_alloc;
+pub mod redzone;
+pub mod reentrancy;
+pub mod slab_alloc;
 #[cfg(test)]
 mod tests;
+mod zeroize;
+pub mod zone_alloc;
 
-pub use crate::buddy_alloc::BuddyAllocParam;
+pub use crate::buddy_alloc::{BuddyAllocParam, Placement};
+pub use crate::chunked_fast_alloc::ChunkedFastAllocParam;
+pub use crate::corruption::HeapCorruption;
 pub use crate::fast_alloc::FastAllocParam;
+pub use crate::handle_alloc::HandleAllocParam;
 pub use crate::non_threadsafe_alloc::NonThreadsafeAlloc;
+pub use crate::oom::{OomAction, OomPolicy};
+pub use crate::poison::Poison;
+pub use crate::raw_alloc::{RawAlloc, RawGlobalAlloc};
+pub use crate::reentrancy::ReentrancyPolicy;
+pub use crate::slab_alloc::{SizeClassParam, SlabAllocParam};
+pub use crate::zone_alloc::{ZoneAllocParam, ZoneFlags, ZoneParam};
@@ -1,10 +1,16 @@
 //! NonThreadSafeAlloc
 //! An allocator that does not support thread-safe
 
-use crate::buddy_alloc::{BuddyAlloc, BuddyAllocParam};
+use crate::buddy_alloc::{BuddyAlloc, BuddyAllocParam, Stats};
+use crate::chunked_fast_alloc::{ChunkedFastAlloc, ChunkedFastAllocParam};
 use crate::fast_alloc::{FastAlloc, FastAllocParam, BLOCK_SIZE};
+use crate::oom::{self, OomAction, OomPolicy};
+use crate::poison::Poison;
+use crate::redzone::{self, RedzoneCorruption};
+use crate::reentrancy::{BusyGuard, DeferredFrees, ReentrancyPolicy};
+use crate::slab_alloc::{SlabAlloc, SlabAllocParam, SlabFallback};
 use core::alloc::{GlobalAlloc, Layout};
-use core::cell::RefCell;
+use core::cell::{Cell, RefCell};
 
 /// Use buddy allocator if request bytes is large than this,
 /// otherwise use fast allocator
@@ -13,52 +19,425 @@ const MAX_FAST_ALLOC_SIZE: usize = BLOCK_SIZE;
 /// NonThreadsafeAlloc
 /// perfect for single threaded devices
 pub struct NonThreadsafeAlloc {
-    fast_alloc_param: FastAllocParam,
+    fast_alloc_param: Option<FastAllocParam>,
     inner_fast_alloc: RefCell<Option<FastAlloc>>,
-    buddy_alloc_param: BuddyAllocParam,
+    slab_alloc_param: Option<SlabAllocParam>,
+    inner_slab_alloc: RefCell<Option<SlabAlloc>>,
+    chunked_fast_alloc_param: Option<ChunkedFastAllocParam>,
+    inner_chunked_fast_alloc: RefCell<Option<ChunkedFastAlloc>>,
+    buddy_alloc_param: Option<BuddyAllocParam>,
     inner_buddy_alloc: RefCell<Option<BuddyAlloc>>,
+    /// false for `empty` allocators until `init` is called
+    initialized: Cell<bool>,
+    uninit_hook: Option<fn(Layout)>,
+    /// modes applied to the params of `init`
+    poison: Option<Poison>,
+    zeroize: bool,
+    /// bytes of each redzone, 0 means disabled
+    redzone: usize,
+    /// live guarded allocations
+    redzone_list: Cell<*mut redzone::Header>,
+    oom_policy: OomPolicy,
+    /// the heap of `OomPolicy::EmergencyReserve`
+    inner_reserve_alloc: RefCell<Option<BuddyAlloc>>,
+    /// set while a request is being served, requests made meanwhile are nested
+    busy: Cell<bool>,
+    deferred_frees: DeferredFrees,
+    reentrancy_policy: ReentrancyPolicy,
+    /// the heap of `ReentrancyPolicy::Reserve`
+    inner_nested_alloc: RefCell<Option<BuddyAlloc>>,
 }
 
 impl NonThreadsafeAlloc {
-    /// see BuddyAlloc::new
-    pub const fn new(fast_alloc_param: FastAllocParam, buddy_alloc_param: BuddyAllocParam) -> Self {
+    /// An uninitialized allocator, for heaps whose bounds are only known at runtime.
+    /// Allocations return null until `init` is called, see `with_uninit_hook`.
+    pub const fn empty() -> Self {
         NonThreadsafeAlloc {
             inner_fast_alloc: RefCell::new(None),
+            inner_slab_alloc: RefCell::new(None),
+            inner_chunked_fast_alloc: RefCell::new(None),
             inner_buddy_alloc: RefCell::new(None),
-            fast_alloc_param,
-            buddy_alloc_param,
+            fast_alloc_param: None,
+            slab_alloc_param: None,
+            chunked_fast_alloc_param: None,
+            buddy_alloc_param: None,
+            initialized: Cell::new(false),
+            uninit_hook: None,
+            poison: None,
+            zeroize: false,
+            redzone: 0,
+            redzone_list: Cell::new(core::ptr::null_mut()),
+            oom_policy: OomPolicy::ReturnNull,
+            inner_reserve_alloc: RefCell::new(None),
+            busy: Cell::new(false),
+            deferred_frees: DeferredFrees::new(),
+            reentrancy_policy: ReentrancyPolicy::ReturnNull,
+            inner_nested_alloc: RefCell::new(None),
+        }
+    }
+
+    /// see BuddyAlloc::new
+    pub const fn new(fast_alloc_param: FastAllocParam, buddy_alloc_param: BuddyAllocParam) -> Self {
+        let mut alloc = Self::empty();
+        alloc.fast_alloc_param = Some(fast_alloc_param);
+        alloc.buddy_alloc_param = Some(buddy_alloc_param);
+        alloc.initialized = Cell::new(true);
+        alloc
+    }
+
+    /// Use size classes instead of the fast allocator for small requests,
+    /// requests no size class fits are served by the buddy allocator,
+    /// see `SlabAllocParam` for the fallback policy of exhausted classes.
+    pub const fn new_with_size_classes(
+        slab_alloc_param: SlabAllocParam,
+        buddy_alloc_param: BuddyAllocParam,
+    ) -> Self {
+        let mut alloc = Self::empty();
+        alloc.slab_alloc_param = Some(slab_alloc_param);
+        alloc.buddy_alloc_param = Some(buddy_alloc_param);
+        alloc.initialized = Cell::new(true);
+        alloc
+    }
+
+    /// Use one region for both allocators, small requests are served by
+    /// chunks carved from the buddy allocator on demand,
+    /// a chunk is given back to the buddy allocator once all its blocks are free.
+    pub const fn new_with_fast_refill(
+        chunked_fast_alloc_param: ChunkedFastAllocParam,
+        buddy_alloc_param: BuddyAllocParam,
+    ) -> Self {
+        let mut alloc = Self::empty();
+        alloc.chunked_fast_alloc_param = Some(chunked_fast_alloc_param);
+        alloc.buddy_alloc_param = Some(buddy_alloc_param);
+        alloc.initialized = Cell::new(true);
+        alloc
+    }
+
+    /// Initializes an `empty` allocator, builds both allocators eagerly.
+    /// Poison and zeroize modes configured on the empty allocator are applied to the params.
+    ///
+    /// # Safety
+    ///
+    /// See `FastAlloc::new` and `BuddyAlloc::new`.
+    /// Must be called once, before any allocation, it panics if the allocator is initialized.
+    pub unsafe fn init(
+        &self,
+        fast_alloc_param: FastAllocParam,
+        buddy_alloc_param: BuddyAllocParam,
+    ) {
+        assert!(
+            !self.initialized.get(),
+            "NonThreadsafeAlloc is already initialized"
+        );
+        let mut fast_alloc_param = fast_alloc_param;
+        if let Some(poison) = self.poison {
+            fast_alloc_param = fast_alloc_param.with_poison(poison);
+        }
+        if self.zeroize {
+            fast_alloc_param = fast_alloc_param.with_zeroize();
+        }
+        *self.inner_fast_alloc.borrow_mut() = Some(FastAlloc::new(fast_alloc_param));
+        *self.inner_buddy_alloc.borrow_mut() =
+            Some(BuddyAlloc::new(self.with_modes(buddy_alloc_param)));
+        self.initialized.set(true);
+    }
+
+    /// Called by allocations before `init`, which return null after the hook returns.
+    pub const fn with_uninit_hook(mut self, hook: fn(Layout)) -> Self {
+        self.uninit_hook = Some(hook);
+        self
+    }
+
+    /// Returns true if the allocator can serve requests
+    pub fn is_initialized(&self) -> bool {
+        self.initialized.get()
+    }
+
+    /// Debug mode: pads each allocation with `size` bytes of redzones on both sides.
+    /// Redzones are verified on `dealloc`, which panics on corruption.
+    pub const fn with_redzone(mut self, size: usize) -> Self {
+        self.redzone = size;
+        self
+    }
+
+    /// Debug mode: poison memory of both allocators, see `Poison`
+    pub const fn with_poison(mut self, poison: Poison) -> Self {
+        if let Some(param) = self.fast_alloc_param {
+            self.fast_alloc_param = Some(param.with_poison(poison));
+        }
+        if let Some(param) = self.slab_alloc_param {
+            self.slab_alloc_param = Some(param.with_poison(poison));
+        }
+        if let Some(param) = self.chunked_fast_alloc_param {
+            self.chunked_fast_alloc_param = Some(param.with_poison(poison));
+        }
+        if let Some(param) = self.buddy_alloc_param {
+            self.buddy_alloc_param = Some(param.with_poison(poison));
+        }
+        self.poison = Some(poison);
+        self
+    }
+
+    /// Secure mode: wipe memory of both allocators on free,
+    /// see `BuddyAllocParam::with_zeroize`
+    pub const fn with_zeroize(mut self) -> Self {
+        if let Some(param) = self.fast_alloc_param {
+            self.fast_alloc_param = Some(param.with_zeroize());
+        }
+        if let Some(param) = self.slab_alloc_param {
+            self.slab_alloc_param = Some(param.with_zeroize());
+        }
+        if let Some(param) = self.chunked_fast_alloc_param {
+            self.chunked_fast_alloc_param = Some(param.with_zeroize());
+        }
+        if let Some(param) = self.buddy_alloc_param {
+            self.buddy_alloc_param = Some(param.with_zeroize());
+        }
+        self.zeroize = true;
+        self
+    }
+
+    /// What to do if a request can't be served, see `OomPolicy`
+    pub const fn with_oom_policy(mut self, policy: OomPolicy) -> Self {
+        self.oom_policy = policy;
+        self
+    }
+
+    /// What to do with requests made while the allocator is in use,
+    /// e.g. from an interrupt handler, see `ReentrancyPolicy`.
+    /// Nested deallocs are deferred until the allocator is released.
+    pub const fn with_reentrancy_policy(mut self, policy: ReentrancyPolicy) -> Self {
+        self.reentrancy_policy = policy;
+        self
+    }
+
+    /// Returns a snapshot of the usage of the buddy allocator,
+    /// returns empty stats if the allocator is in use.
+    pub fn stats(&self) -> Stats {
+        self.exclusive(|| self.buddy_stats()).unwrap_or_default()
+    }
+
+    fn buddy_stats(&self) -> Stats {
+        if !self.is_initialized() {
+            return Stats::default();
         }
+        unsafe { self.with_buddy_alloc(|alloc| alloc.stats()) }
+    }
+
+    /// Runs f with the allocator marked as in use,
+    /// returns None if the allocator is already in use, i.e. f would be a nested request.
+    pub(crate) fn exclusive<R, F: FnOnce() -> R>(&self, f: F) -> Option<R> {
+        let _guard = BusyGuard::enter(&self.busy)?;
+        let r = f();
+        // free the blocks of nested deallocs made during f
+        while let Some((ptr, layout)) = self.deferred_frees.pop() {
+            unsafe { self.dealloc_exclusive(ptr, layout) };
+        }
+        Some(r)
+    }
+
+    /// Verifies redzones of all live allocations,
+    /// returns the first corruption found.
+    pub fn check_all_redzones(&self) -> Result<(), RedzoneCorruption> {
+        redzone::check_all(&self.redzone_list)
     }
 
-    unsafe fn with_fast_alloc<R, F: FnOnce(&mut FastAlloc) -> R>(&self, f: F) -> R {
+    /// returns None if the fast allocator is not used
+    unsafe fn with_fast_alloc<R, F: FnOnce(&mut FastAlloc) -> R>(&self, f: F) -> Option<R> {
         let mut inner = self.inner_fast_alloc.borrow_mut();
-        let alloc = inner.get_or_insert_with(|| FastAlloc::new(self.fast_alloc_param));
-        f(alloc)
+        // built by `init` or on first use
+        if inner.is_none() {
+            *inner = Some(FastAlloc::new(self.fast_alloc_param?));
+        }
+        inner.as_mut().map(f)
+    }
+
+    /// returns None if size classes are not used
+    unsafe fn with_slab_alloc<R, F: FnOnce(&mut SlabAlloc) -> R>(&self, f: F) -> Option<R> {
+        let param = self.slab_alloc_param?;
+        let mut inner = self.inner_slab_alloc.borrow_mut();
+        let alloc = inner.get_or_insert_with(|| SlabAlloc::new(param));
+        Some(f(alloc))
+    }
+
+    /// returns None if chunks are not used
+    unsafe fn with_chunked_fast_alloc<R, F: FnOnce(&mut ChunkedFastAlloc, &mut BuddyAlloc) -> R>(
+        &self,
+        f: F,
+    ) -> Option<R> {
+        let param = self.chunked_fast_alloc_param?;
+        let mut inner = self.inner_chunked_fast_alloc.borrow_mut();
+        let alloc = inner.get_or_insert_with(|| ChunkedFastAlloc::new(param));
+        Some(self.with_buddy_alloc(|buddy| f(alloc, buddy)))
+    }
+
+    /// try allocators of small requests,
+    /// returns None if the request should be served by the buddy allocator
+    unsafe fn alloc_small(&self, layout: Layout) -> Option<*mut u8> {
+        let bytes = layout.size();
+        let slab = self.with_slab_alloc(|alloc| {
+            let p = alloc.malloc(layout);
+            // the policy may fail the request rather than fallback to BuddyAlloc
+            let fail = alloc.serves(layout) && alloc.fallback() == SlabFallback::Fail;
+            if p.is_null() && !fail {
+                None
+            } else {
+                Some(p)
+            }
+        });
+        if let Some(p) = slab {
+            return p;
+        }
+        // use BuddyAlloc if size is larger than MAX_FAST_ALLOC_SIZE
+        if bytes > MAX_FAST_ALLOC_SIZE {
+            return None;
+        }
+        // try fast alloc, fallback to BuddyAlloc if failed
+        self.with_chunked_fast_alloc(|alloc, buddy| alloc.malloc(bytes, buddy))
+            .or_else(|| self.with_fast_alloc(|alloc| alloc.malloc(bytes)))
+            .filter(|p| !p.is_null())
     }
 
     unsafe fn with_buddy_alloc<R, F: FnOnce(&mut BuddyAlloc) -> R>(&self, f: F) -> R {
         let mut inner = self.inner_buddy_alloc.borrow_mut();
-        let alloc = inner.get_or_insert_with(|| BuddyAlloc::new(self.buddy_alloc_param));
+        let alloc = inner.get_or_insert_with(|| {
+            BuddyAlloc::new(
+                self.buddy_alloc_param
+                    .expect("NonThreadsafeAlloc is not initialized"),
+            )
+        });
         f(alloc)
     }
-}
 
-unsafe impl GlobalAlloc for NonThreadsafeAlloc {
-    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+    /// applies the poison and zeroize modes to the param of a heap built at runtime
+    fn with_modes(&self, mut param: BuddyAllocParam) -> BuddyAllocParam {
+        if let Some(poison) = self.poison {
+            param = param.with_poison(poison);
+        }
+        if self.zeroize {
+            param = param.with_zeroize();
+        }
+        param
+    }
+
+    /// returns None if the emergency reserve is not used, builds the reserve on OOM
+    unsafe fn with_reserve_alloc<R, F: FnOnce(&mut BuddyAlloc) -> R>(&self, f: F) -> Option<R> {
+        let param = match self.oom_policy {
+            OomPolicy::EmergencyReserve(param) => param,
+            _ => return None,
+        };
+        let mut inner = self.inner_reserve_alloc.borrow_mut();
+        let alloc = inner.get_or_insert_with(|| BuddyAlloc::new(self.with_modes(param)));
+        Some(f(alloc))
+    }
+
+    /// returns None if the emergency reserve is not built, it's only built on OOM
+    fn with_built_reserve_alloc<R, F: FnOnce(&mut BuddyAlloc) -> R>(&self, f: F) -> Option<R> {
+        self.inner_reserve_alloc.borrow_mut().as_mut().map(f)
+    }
+
+    unsafe fn alloc_inner(&self, layout: Layout, zeroed: bool) -> *mut u8 {
+        if !self.is_initialized() {
+            if let Some(hook) = self.uninit_hook {
+                hook(layout);
+            }
+            return core::ptr::null_mut();
+        }
+        loop {
+            let p = self.try_alloc(layout, zeroed);
+            if !p.is_null() {
+                return p;
+            }
+            // no borrows are held here, the handler may use the allocator
+            match self.oom_policy {
+                OomPolicy::ReturnNull => return p,
+                OomPolicy::Panic => oom::oom_panic(layout, self.buddy_stats()),
+                OomPolicy::EmergencyReserve(_) => {
+                    return self
+                        .with_reserve_alloc(|alloc| {
+                            if zeroed {
+                                alloc.malloc_zeroed(layout.size())
+                            } else {
+                                alloc.malloc(layout.size())
+                            }
+                        })
+                        .unwrap_or(p);
+                }
+                OomPolicy::Callback(handler) => {
+                    let stats = self.buddy_stats();
+                    // release the allocator, so the handler can free memory
+                    self.busy.set(false);
+                    let action = handler(layout, stats);
+                    self.busy.set(true);
+                    if action == OomAction::Fail {
+                        return p;
+                    }
+                }
+            }
+        }
+    }
+
+    unsafe fn try_alloc(&self, layout: Layout, zeroed: bool) -> *mut u8 {
         let bytes = layout.size();
-        // use BuddyAlloc if size is larger than MAX_FAST_ALLOC_SIZE
-        if bytes > MAX_FAST_ALLOC_SIZE {
-            self.with_buddy_alloc(|alloc| alloc.malloc(bytes))
-        } else {
-            // try fast alloc, fallback to BuddyAlloc if failed
-            let mut p = self.with_fast_alloc(|alloc| alloc.malloc(bytes));
-            if p.is_null() {
-                p = self.with_buddy_alloc(|alloc| alloc.malloc(bytes));
+        let buddy_malloc = |alloc: &mut BuddyAlloc| {
+            if zeroed {
+                // BuddyAlloc skips filling zeros for known zero filled blocks
+                alloc.malloc_zeroed(bytes)
+            } else {
+                alloc.malloc(bytes)
+            }
+        };
+        match self.alloc_small(layout) {
+            Some(p) => {
+                if zeroed && !p.is_null() {
+                    core::ptr::write_bytes(p, 0, bytes);
+                }
+                p
             }
-            p
+            None => self.with_buddy_alloc(buddy_malloc),
         }
     }
-    unsafe fn dealloc(&self, ptr: *mut u8, _layout: Layout) {
+
+    /// usable size of a block served by allocators of small requests,
+    /// returns None if the block is served by the buddy allocator
+    unsafe fn small_usable_size(&self, ptr: *mut u8) -> Option<usize> {
+        self.with_slab_alloc(|alloc| alloc.contains_ptr(ptr).then(|| alloc.usable_size(ptr)))
+            .flatten()
+            .or_else(|| {
+                self.with_chunked_fast_alloc(|alloc, buddy| {
+                    alloc.contains_ptr(ptr, buddy).then_some(BLOCK_SIZE)
+                })
+                .flatten()
+            })
+            .or_else(|| {
+                self.with_fast_alloc(|alloc| alloc.contains_ptr(ptr).then(|| alloc.block_size()))
+                    .flatten()
+            })
+    }
+
+    unsafe fn dealloc_inner(&self, ptr: *mut u8, layout: Layout) {
+        let freed = self.with_slab_alloc(|alloc| {
+            if alloc.contains_ptr(ptr) {
+                alloc.free(ptr, layout);
+                true
+            } else {
+                false
+            }
+        });
+        if freed == Some(true) {
+            return;
+        }
+        let freed = self.with_chunked_fast_alloc(|alloc, buddy| {
+            if alloc.contains_ptr(ptr, buddy) {
+                alloc.free(ptr, buddy);
+                true
+            } else {
+                false
+            }
+        });
+        if freed == Some(true) {
+            return;
+        }
         let freed = self.with_fast_alloc(|alloc| {
             if alloc.contains_ptr(ptr) {
                 alloc.free(ptr);
@@ -67,10 +446,179 @@ unsafe impl GlobalAlloc for NonThreadsafeAlloc {
                 false
             }
         });
-        if !freed {
+        if freed == Some(true) {
+            return;
+        }
+        let freed = self.with_built_reserve_alloc(|alloc| {
+            if alloc.contains_ptr(ptr) {
+                alloc.free(ptr);
+                true
+            } else {
+                false
+            }
+        });
+        if freed != Some(true) {
             self.with_buddy_alloc(|alloc| alloc.free(ptr));
         }
     }
+
+    /// returns true if the block is served by the emergency reserve
+    unsafe fn in_reserve(&self, ptr: *mut u8) -> bool {
+        self.with_built_reserve_alloc(|alloc| alloc.contains_ptr(ptr)) == Some(true)
+    }
+
+    /// returns None if the reentrancy reserve is not used or is in use,
+    /// builds the reserve on a nested request
+    unsafe fn with_nested_alloc<R, F: FnOnce(&mut BuddyAlloc) -> R>(&self, f: F) -> Option<R> {
+        let param = match self.reentrancy_policy {
+            ReentrancyPolicy::Reserve(param) => param,
+            _ => return None,
+        };
+        let mut inner = self.inner_nested_alloc.try_borrow_mut().ok()?;
+        let alloc = inner.get_or_insert_with(|| BuddyAlloc::new(self.with_modes(param)));
+        Some(f(alloc))
+    }
+
+    /// returns None if the reentrancy reserve is not built or is in use,
+    /// it's only built by nested requests
+    fn with_built_nested_alloc<R, F: FnOnce(&mut BuddyAlloc) -> R>(&self, f: F) -> Option<R> {
+        self.inner_nested_alloc
+            .try_borrow_mut()
+            .ok()?
+            .as_mut()
+            .map(f)
+    }
+
+    /// returns true if the block is served by the reentrancy reserve
+    unsafe fn in_nested_reserve(&self, ptr: *mut u8) -> bool {
+        self.with_built_nested_alloc(|alloc| alloc.contains_ptr(ptr)) == Some(true)
+    }
+
+    /// frees the block if it is served by the reentrancy reserve
+    unsafe fn free_nested(&self, ptr: *mut u8) -> bool {
+        let freed = self.with_built_nested_alloc(|alloc| {
+            if alloc.contains_ptr(ptr) {
+                alloc.free(ptr);
+                true
+            } else {
+                false
+            }
+        });
+        freed == Some(true)
+    }
+
+    unsafe fn alloc_nested(&self, layout: Layout, zeroed: bool) -> *mut u8 {
+        match self.reentrancy_policy {
+            ReentrancyPolicy::ReturnNull => core::ptr::null_mut(),
+            ReentrancyPolicy::Hook(hook) => {
+                hook(layout);
+                core::ptr::null_mut()
+            }
+            ReentrancyPolicy::Reserve(_) => self
+                .with_nested_alloc(|alloc| {
+                    if zeroed {
+                        alloc.malloc_zeroed(layout.size())
+                    } else {
+                        alloc.malloc(layout.size())
+                    }
+                })
+                .unwrap_or(core::ptr::null_mut()),
+        }
+    }
+
+    unsafe fn dealloc_nested(&self, ptr: *mut u8, layout: Layout) {
+        // the block is leaked if the deferred list is full
+        if !self.free_nested(ptr) {
+            self.deferred_frees.push(ptr, layout);
+        }
+    }
+
+    unsafe fn alloc_exclusive(&self, layout: Layout, zeroed: bool) -> *mut u8 {
+        if self.redzone == 0 {
+            return self.alloc_inner(layout, zeroed);
+        }
+        let padded = match redzone::padded_layout(layout, self.redzone) {
+            Some(padded) => padded,
+            None => return core::ptr::null_mut(),
+        };
+        let block = self.alloc_inner(padded, false);
+        if block.is_null() {
+            return block;
+        }
+        let p = redzone::arm(block, layout, self.redzone, &self.redzone_list);
+        if zeroed {
+            core::ptr::write_bytes(p, 0, layout.size());
+        }
+        p
+    }
+
+    unsafe fn dealloc_exclusive(&self, ptr: *mut u8, layout: Layout) {
+        // blocks of the reentrancy reserve have no redzones
+        if self.free_nested(ptr) {
+            return;
+        }
+        if self.redzone == 0 {
+            return self.dealloc_inner(ptr, layout);
+        }
+        let padded = redzone::padded_layout(layout, self.redzone).expect("padded layout");
+        match redzone::disarm(ptr, layout, self.redzone, &self.redzone_list) {
+            Ok(block) => self.dealloc_inner(block, padded),
+            Err(err) => panic!("{}", err),
+        }
+    }
+
+    unsafe fn realloc_exclusive(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        // keep the block if it is large enough, or can be grown in place,
+        // blocks of both reserves are always migrated back to the main heap
+        if self.redzone == 0 && !self.in_reserve(ptr) && !self.in_nested_reserve(ptr) {
+            let resized = match self.small_usable_size(ptr) {
+                Some(usable_size) => new_size <= usable_size,
+                None => self.with_buddy_alloc(|alloc| alloc.grow_in_place(ptr, new_size)),
+            };
+            if resized {
+                return ptr;
+            }
+        }
+        // migrate to a new block
+        let new_layout = Layout::from_size_align_unchecked(new_size, layout.align());
+        let new_ptr = self.alloc_exclusive(new_layout, false);
+        if !new_ptr.is_null() {
+            core::ptr::copy_nonoverlapping(ptr, new_ptr, core::cmp::min(layout.size(), new_size));
+            self.dealloc_exclusive(ptr, layout);
+        }
+        new_ptr
+    }
+}
+
+unsafe impl GlobalAlloc for NonThreadsafeAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        self.exclusive(|| self.alloc_exclusive(layout, false))
+            .unwrap_or_else(|| self.alloc_nested(layout, false))
+    }
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        self.exclusive(|| self.alloc_exclusive(layout, true))
+            .unwrap_or_else(|| self.alloc_nested(layout, true))
+    }
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        if self
+            .exclusive(|| self.dealloc_exclusive(ptr, layout))
+            .is_none()
+        {
+            self.dealloc_nested(ptr, layout);
+        }
+    }
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        if let Some(new_ptr) = self.exclusive(|| self.realloc_exclusive(ptr, layout, new_size)) {
+            return new_ptr;
+        }
+        let new_layout = Layout::from_size_align_unchecked(new_size, layout.align());
+        let new_ptr = self.alloc_nested(new_layout, false);
+        if !new_ptr.is_null() {
+            core::ptr::copy_nonoverlapping(ptr, new_ptr, core::cmp::min(layout.size(), new_size));
+            self.dealloc_nested(ptr, layout);
+        }
+        new_ptr
+    }
 }
 
 unsafe impl Sync for NonThreadsafeAlloc {}
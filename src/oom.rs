@@ -0,0 +1,44 @@
+//! Out of memory handling
+//! What NonThreadsafeAlloc does when no allocator can serve a request.
+
+use crate::buddy_alloc::{BuddyAllocParam, Stats};
+use core::alloc::Layout;
+
+/// What to do after an OOM handler returns
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OomAction {
+    /// Try the request again, e.g. the handler has released some caches
+    Retry,
+    /// Give up, the allocation returns null
+    Fail,
+}
+
+/// A user handler, called with the failed layout and the stats of the buddy allocator.
+/// The allocator is not borrowed while the handler runs, so it may free memory.
+pub type OomHandler = fn(Layout, Stats) -> OomAction;
+
+#[derive(Clone, Copy, Default)]
+pub enum OomPolicy {
+    /// Return null, the default behavior
+    #[default]
+    ReturnNull,
+    /// Panic with the failed layout and the stats of the buddy allocator,
+    /// which needs `panic = "abort"`, see the crate docs.
+    Panic,
+    /// Serve the request from a reserved heap,
+    /// the reserve is only touched after the main heap fails.
+    /// Poison and zeroize modes of the allocator are applied to the reserve.
+    EmergencyReserve(BuddyAllocParam),
+    /// Call a user handler, which decides to retry or fail the request.
+    /// Note the request is retried as long as the handler returns `OomAction::Retry`.
+    Callback(OomHandler),
+}
+
+pub(crate) fn oom_panic(layout: Layout, stats: Stats) -> ! {
+    panic!(
+        "out of memory: failed to allocate {} bytes (align {}), {}",
+        layout.size(),
+        layout.align(),
+        stats
+    )
+}
@@ -0,0 +1,65 @@
+//! Poison
+//! Fills memory with recognizable patterns, so reads of uninitialized memory
+//! and writes to freed memory can be spotted.
+
+/// Default pattern written to freshly allocated blocks
+pub const ALLOC_PATTERN: u8 = 0xAA;
+/// Default pattern written to freed blocks
+pub const FREE_PATTERN: u8 = 0xDD;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Poison {
+    /// Alloc pattern: written to blocks on allocation
+    pub alloc_pattern: u8,
+    /// Free pattern: written to blocks on free, the free list node header is skipped
+    pub free_pattern: u8,
+    /// Check on alloc: verify a block taken from a free list still holds the free pattern,
+    /// panic if it was written after free.
+    pub check_on_alloc: bool,
+}
+
+impl Poison {
+    /// Use the default patterns `ALLOC_PATTERN` and `FREE_PATTERN`
+    pub const fn new(check_on_alloc: bool) -> Self {
+        Poison {
+            alloc_pattern: ALLOC_PATTERN,
+            free_pattern: FREE_PATTERN,
+            check_on_alloc,
+        }
+    }
+
+    /// Similar to new, but with custom patterns
+    pub const fn new_with_patterns(
+        alloc_pattern: u8,
+        free_pattern: u8,
+        check_on_alloc: bool,
+    ) -> Self {
+        Poison {
+            alloc_pattern,
+            free_pattern,
+            check_on_alloc,
+        }
+    }
+
+    /// Panics if bytes in `(block + skip)..(block + len)` do not hold the free pattern.
+    pub(crate) fn check_freed(&self, block: *const u8, skip: usize, len: usize) {
+        if !self.check_on_alloc {
+            return;
+        }
+        if let Some(i) = (skip..len).find(|&i| unsafe { *block.add(i) } != self.free_pattern) {
+            panic!(
+                "use after free: block {:p} was modified at {:p} after free",
+                block,
+                block.wrapping_add(i)
+            );
+        }
+    }
+
+    pub(crate) fn fill_alloc(&self, p: *mut u8, len: usize) {
+        unsafe { core::ptr::write_bytes(p, self.alloc_pattern, len) };
+    }
+
+    pub(crate) fn fill_free(&self, p: *mut u8, len: usize) {
+        unsafe { core::ptr::write_bytes(p, self.free_pattern, len) };
+    }
+}
@@ -0,0 +1,300 @@
+//! RawAlloc
+//! A common interface of the allocators, and combinators to build allocator stacks from them.
+//!
+//! ```ignore
+//! // a fast allocator in front of a buddy heap, and an emergency heap behind both
+//! type Stack = Fallback<Segregator<64, FastAlloc, BuddyAlloc>, BuddyAlloc>;
+//! ```
+
+use crate::buddy_alloc::BuddyAlloc;
+use crate::fast_alloc::GenericFastAlloc;
+use crate::slab_alloc::SlabAlloc;
+use core::alloc::{GlobalAlloc, Layout};
+use core::cell::RefCell;
+
+pub trait RawAlloc {
+    /// Returns null if the request can't be served
+    fn malloc(&mut self, layout: Layout) -> *mut u8;
+    /// `p` must be returned by `malloc` of this allocator with the same layout
+    fn free(&mut self, p: *mut u8, layout: Layout);
+    /// Returns true if p is inside the memory managed by this allocator
+    fn contains(&self, p: *mut u8) -> bool;
+    /// Returns the granted bytes of the block p points to
+    fn usable_size(&self, p: *mut u8) -> usize;
+}
+
+/// Gives the block back if it does not meet the alignment of layout
+fn aligned_or_free<A: RawAlloc>(alloc: &mut A, p: *mut u8, layout: Layout) -> *mut u8 {
+    if p as usize & (layout.align() - 1) != 0 {
+        alloc.free(p, layout);
+        return core::ptr::null_mut();
+    }
+    p
+}
+
+/// Any alignment is served, larger alignments than the leaf size may cost
+/// `align - leaf size` more bytes, see `BuddyAlloc::malloc_aligned`
+impl RawAlloc for BuddyAlloc {
+    fn malloc(&mut self, layout: Layout) -> *mut u8 {
+        BuddyAlloc::malloc_aligned(self, layout.size(), layout.align())
+    }
+
+    fn free(&mut self, p: *mut u8, _layout: Layout) {
+        BuddyAlloc::free(self, p)
+    }
+
+    fn contains(&self, p: *mut u8) -> bool {
+        self.contains_ptr(p)
+    }
+
+    fn usable_size(&self, p: *mut u8) -> usize {
+        BuddyAlloc::usable_size(self, p)
+    }
+}
+
+impl<const BLOCK: usize> RawAlloc for GenericFastAlloc<BLOCK> {
+    fn malloc(&mut self, layout: Layout) -> *mut u8 {
+        if layout.align() > BLOCK {
+            return core::ptr::null_mut();
+        }
+        let p = GenericFastAlloc::malloc(self, layout.size());
+        if p.is_null() {
+            return p;
+        }
+        aligned_or_free(self, p, layout)
+    }
+
+    fn free(&mut self, p: *mut u8, _layout: Layout) {
+        GenericFastAlloc::free(self, p)
+    }
+
+    fn contains(&self, p: *mut u8) -> bool {
+        self.contains_ptr(p)
+    }
+
+    fn usable_size(&self, _p: *mut u8) -> usize {
+        BLOCK
+    }
+}
+
+impl RawAlloc for SlabAlloc {
+    fn malloc(&mut self, layout: Layout) -> *mut u8 {
+        SlabAlloc::malloc(self, layout)
+    }
+
+    fn free(&mut self, p: *mut u8, layout: Layout) {
+        SlabAlloc::free(self, p, layout)
+    }
+
+    fn contains(&self, p: *mut u8) -> bool {
+        self.contains_ptr(p)
+    }
+
+    fn usable_size(&self, p: *mut u8) -> usize {
+        SlabAlloc::usable_size(self, p)
+    }
+}
+
+/// Tries the primary allocator, then the fallback allocator.
+/// Blocks are given back to their owner, found by address.
+pub struct Fallback<A, B> {
+    pub primary: A,
+    pub fallback: B,
+}
+
+impl<A, B> Fallback<A, B> {
+    pub const fn new(primary: A, fallback: B) -> Self {
+        Fallback { primary, fallback }
+    }
+}
+
+impl<A: RawAlloc, B: RawAlloc> RawAlloc for Fallback<A, B> {
+    fn malloc(&mut self, layout: Layout) -> *mut u8 {
+        let p = self.primary.malloc(layout);
+        if !p.is_null() {
+            return p;
+        }
+        self.fallback.malloc(layout)
+    }
+
+    fn free(&mut self, p: *mut u8, layout: Layout) {
+        if self.primary.contains(p) {
+            self.primary.free(p, layout)
+        } else {
+            self.fallback.free(p, layout)
+        }
+    }
+
+    fn contains(&self, p: *mut u8) -> bool {
+        self.primary.contains(p) || self.fallback.contains(p)
+    }
+
+    fn usable_size(&self, p: *mut u8) -> usize {
+        if self.primary.contains(p) {
+            self.primary.usable_size(p)
+        } else {
+            self.fallback.usable_size(p)
+        }
+    }
+}
+
+/// Serves requests of at most `N` bytes by the small allocator, others by the large allocator.
+/// Requests are routed by the size of their layouts, the small allocator never falls back.
+/// Blocks are given back to their owner, found by address, since a block kept in place
+/// by `realloc` may be freed with a layout of the other side of `N`.
+pub struct Segregator<const N: usize, Small, Large> {
+    pub small: Small,
+    pub large: Large,
+}
+
+impl<const N: usize, Small, Large> Segregator<N, Small, Large> {
+    pub const fn new(small: Small, large: Large) -> Self {
+        Segregator { small, large }
+    }
+}
+
+impl<const N: usize, Small: RawAlloc, Large: RawAlloc> RawAlloc for Segregator<N, Small, Large> {
+    fn malloc(&mut self, layout: Layout) -> *mut u8 {
+        if layout.size() <= N {
+            self.small.malloc(layout)
+        } else {
+            self.large.malloc(layout)
+        }
+    }
+
+    fn free(&mut self, p: *mut u8, layout: Layout) {
+        if self.small.contains(p) {
+            self.small.free(p, layout)
+        } else {
+            self.large.free(p, layout)
+        }
+    }
+
+    fn contains(&self, p: *mut u8) -> bool {
+        self.small.contains(p) || self.large.contains(p)
+    }
+
+    fn usable_size(&self, p: *mut u8) -> usize {
+        if self.small.contains(p) {
+            self.small.usable_size(p)
+        } else {
+            self.large.usable_size(p)
+        }
+    }
+}
+
+/// Spreads requests over `BUCKETS` allocators by size,
+/// bucket `i` serves sizes in `(min + i * step + 1)..=(min + (i + 1) * step)`.
+/// Requests out of the buckets' range fail, combine with `Segregator` or `Fallback` to serve them.
+/// Blocks are given back to their owner, found by address.
+pub struct Bucketizer<A, const BUCKETS: usize> {
+    min: usize,
+    step: usize,
+    pub buckets: [A; BUCKETS],
+}
+
+impl<A, const BUCKETS: usize> Bucketizer<A, BUCKETS> {
+    pub const fn new(min: usize, step: usize, buckets: [A; BUCKETS]) -> Self {
+        assert!(step > 0, "step must be positive");
+        Bucketizer { min, step, buckets }
+    }
+
+    /// Returns the index of the bucket serves `size` bytes
+    fn bucket_index(&self, size: usize) -> Option<usize> {
+        let i = size.checked_sub(self.min + 1)? / self.step;
+        if i < BUCKETS {
+            Some(i)
+        } else {
+            None
+        }
+    }
+}
+
+impl<A: RawAlloc, const BUCKETS: usize> RawAlloc for Bucketizer<A, BUCKETS> {
+    fn malloc(&mut self, layout: Layout) -> *mut u8 {
+        match self.bucket_index(layout.size()) {
+            Some(i) => self.buckets[i].malloc(layout),
+            None => core::ptr::null_mut(),
+        }
+    }
+
+    fn free(&mut self, p: *mut u8, layout: Layout) {
+        self.buckets
+            .iter_mut()
+            .find(|bucket| bucket.contains(p))
+            .expect("free a block not belongs to Bucketizer")
+            .free(p, layout)
+    }
+
+    fn contains(&self, p: *mut u8) -> bool {
+        self.buckets.iter().any(|bucket| bucket.contains(p))
+    }
+
+    fn usable_size(&self, p: *mut u8) -> usize {
+        self.buckets
+            .iter()
+            .find(|bucket| bucket.contains(p))
+            .map(|bucket| bucket.usable_size(p))
+            .expect("block not belongs to Bucketizer")
+    }
+}
+
+/// A `GlobalAlloc` adapter of a `RawAlloc`, which does not support thread-safe.
+/// Nested requests, e.g. from interrupt handlers, fail instead of panicking.
+pub struct RawGlobalAlloc<A> {
+    inner: RefCell<Option<A>>,
+}
+
+impl<A: RawAlloc> RawGlobalAlloc<A> {
+    pub const fn new(alloc: A) -> Self {
+        RawGlobalAlloc {
+            inner: RefCell::new(Some(alloc)),
+        }
+    }
+
+    /// An uninitialized adapter, allocations return null until `init` is called
+    pub const fn empty() -> Self {
+        RawGlobalAlloc {
+            inner: RefCell::new(None),
+        }
+    }
+
+    /// Panics if the adapter is initialized
+    pub fn init(&self, alloc: A) {
+        let mut inner = self.inner.borrow_mut();
+        assert!(inner.is_none(), "RawGlobalAlloc is already initialized");
+        *inner = Some(alloc);
+    }
+
+    /// Runs f with the allocator, returns None if it is uninitialized or in use
+    pub fn with_alloc<R, F: FnOnce(&mut A) -> R>(&self, f: F) -> Option<R> {
+        let mut inner = self.inner.try_borrow_mut().ok()?;
+        inner.as_mut().map(f)
+    }
+}
+
+unsafe impl<A: RawAlloc> GlobalAlloc for RawGlobalAlloc<A> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        self.with_alloc(|alloc| alloc.malloc(layout))
+            .unwrap_or(core::ptr::null_mut())
+    }
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        // the block is leaked if the allocator is in use
+        self.with_alloc(|alloc| alloc.free(ptr, layout));
+    }
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let fits = self.with_alloc(|alloc| new_size <= alloc.usable_size(ptr));
+        if fits == Some(true) {
+            return ptr;
+        }
+        let new_layout = Layout::from_size_align_unchecked(new_size, layout.align());
+        let new_ptr = self.alloc(new_layout);
+        if !new_ptr.is_null() {
+            core::ptr::copy_nonoverlapping(ptr, new_ptr, core::cmp::min(layout.size(), new_size));
+            self.dealloc(ptr, layout);
+        }
+        new_ptr
+    }
+}
+
+unsafe impl<A> Sync for RawGlobalAlloc<A> {}
@@ -0,0 +1,173 @@
+//! Redzones
+//! Guard bytes placed before and after each allocation to detect buffer overruns.
+//!
+//! A guarded block is laid out as `header | front redzone | user data | back redzone`.
+//! The header links all live guarded blocks, so they can be verified at any time.
+
+use core::alloc::Layout;
+use core::cell::Cell;
+use core::fmt;
+
+/// The pattern written into redzones
+pub const REDZONE_CANARY: u8 = 0xFD;
+
+/// Which redzone of an allocation was overwritten
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RedzoneSide {
+    /// bytes before the user region, usually an underflow
+    Front,
+    /// bytes after the user region, usually an overflow
+    Back,
+}
+
+/// Reported when the canary of a redzone is damaged
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RedzoneCorruption {
+    /// The pointer returned to the user
+    pub ptr: *mut u8,
+    /// The corrupted side
+    pub side: RedzoneSide,
+    /// Address of the first damaged byte
+    pub addr: *mut u8,
+}
+
+impl fmt::Display for RedzoneCorruption {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let side = match self.side {
+            RedzoneSide::Front => "front",
+            RedzoneSide::Back => "back",
+        };
+        write!(
+            f,
+            "{} redzone of allocation {:p} is corrupted at {:p}",
+            side, self.ptr, self.addr
+        )
+    }
+}
+
+pub(crate) struct Header {
+    next: *mut Header,
+    prev: *mut Header,
+    /// requested bytes
+    size: usize,
+    /// offset from the header to the user region
+    offset: usize,
+    /// back redzone bytes
+    redzone: usize,
+}
+
+const HEADER_SIZE: usize = core::mem::size_of::<Header>();
+
+/// offset from the start of a guarded block to the user region
+fn user_offset(layout: Layout, redzone: usize) -> usize {
+    let align = core::cmp::max(layout.align(), core::mem::align_of::<Header>());
+    (HEADER_SIZE + redzone + align - 1) & !(align - 1)
+}
+
+/// Returns the layout of the guarded block
+pub(crate) fn padded_layout(layout: Layout, redzone: usize) -> Option<Layout> {
+    let size = user_offset(layout, redzone)
+        .checked_add(layout.size())?
+        .checked_add(redzone)?;
+    Layout::from_size_align(size, layout.align()).ok()
+}
+
+/// Fills the redzones of a fresh block, links it into `list`, and returns the user pointer.
+///
+/// # Safety
+///
+/// `block` must point to `padded_layout(layout, redzone)` writable bytes.
+pub(crate) unsafe fn arm(
+    block: *mut u8,
+    layout: Layout,
+    redzone: usize,
+    list: &Cell<*mut Header>,
+) -> *mut u8 {
+    let offset = user_offset(layout, redzone);
+    let header = block.cast::<Header>();
+    let head = list.get();
+    header.write(Header {
+        next: head,
+        prev: core::ptr::null_mut(),
+        size: layout.size(),
+        offset,
+        redzone,
+    });
+    if !head.is_null() {
+        (*head).prev = header;
+    }
+    list.set(header);
+
+    let ptr = block.add(offset);
+    core::ptr::write_bytes(block.add(HEADER_SIZE), REDZONE_CANARY, offset - HEADER_SIZE);
+    core::ptr::write_bytes(ptr.add(layout.size()), REDZONE_CANARY, redzone);
+    ptr
+}
+
+/// Verifies and unlinks the block of `ptr`, returns the start of the guarded block.
+///
+/// # Safety
+///
+/// `ptr` must be returned by `arm` with the same `layout` and `redzone`.
+pub(crate) unsafe fn disarm(
+    ptr: *mut u8,
+    layout: Layout,
+    redzone: usize,
+    list: &Cell<*mut Header>,
+) -> Result<*mut u8, RedzoneCorruption> {
+    let block = ptr.sub(user_offset(layout, redzone));
+    let header = block.cast::<Header>();
+    check(header)?;
+    let Header { next, prev, .. } = header.read();
+    if prev.is_null() {
+        list.set(next);
+    } else {
+        (*prev).next = next;
+    }
+    if !next.is_null() {
+        (*next).prev = prev;
+    }
+    Ok(block)
+}
+
+/// Verifies all live guarded blocks in `list`
+pub(crate) fn check_all(list: &Cell<*mut Header>) -> Result<(), RedzoneCorruption> {
+    let mut header = list.get();
+    while !header.is_null() {
+        unsafe {
+            check(header)?;
+            header = (*header).next;
+        }
+    }
+    Ok(())
+}
+
+unsafe fn check(header: *mut Header) -> Result<(), RedzoneCorruption> {
+    let Header {
+        size,
+        offset,
+        redzone,
+        ..
+    } = header.read();
+    let block = header.cast::<u8>();
+    let ptr = block.add(offset);
+    if let Some(addr) = find_damaged(block.add(HEADER_SIZE), offset - HEADER_SIZE) {
+        return Err(RedzoneCorruption {
+            ptr,
+            side: RedzoneSide::Front,
+            addr,
+        });
+    }
+    if let Some(addr) = find_damaged(ptr.add(size), redzone) {
+        return Err(RedzoneCorruption {
+            ptr,
+            side: RedzoneSide::Back,
+            addr,
+        });
+    }
+    Ok(())
+}
+
+unsafe fn find_damaged(p: *mut u8, len: usize) -> Option<*mut u8> {
+    (0..len).map(|i| p.add(i)).find(|&b| *b != REDZONE_CANARY)
+}
@@ -0,0 +1,80 @@
+//! Reentrancy
+//! Requests made while the allocator is in use, e.g. from an interrupt handler,
+//! a logging hook or a panic handler, are served without touching the busy allocators.
+
+use crate::buddy_alloc::BuddyAllocParam;
+use core::alloc::Layout;
+use core::cell::Cell;
+
+/// Max number of nested deallocs waiting for the allocator,
+/// blocks freed after the list is full are leaked.
+pub const MAX_DEFERRED_FREES: usize = 16;
+
+/// What to do with a nested allocation
+#[derive(Clone, Copy, Default)]
+pub enum ReentrancyPolicy {
+    /// Return null, the default behavior
+    #[default]
+    ReturnNull,
+    /// Call a hook, then return null
+    Hook(fn(Layout)),
+    /// Serve nested requests from a small dedicated heap,
+    /// returns null if the reserve is in use too.
+    /// The reserve is only touched by nested requests.
+    /// Poison and zeroize modes of the allocator are applied to the reserve.
+    Reserve(BuddyAllocParam),
+}
+
+/// Clears the busy flag on drop, also on unwinding
+pub(crate) struct BusyGuard<'a>(&'a Cell<bool>);
+
+impl<'a> BusyGuard<'a> {
+    /// returns None if the flag is already set
+    pub(crate) fn enter(busy: &'a Cell<bool>) -> Option<Self> {
+        if busy.replace(true) {
+            None
+        } else {
+            Some(BusyGuard(busy))
+        }
+    }
+}
+
+impl Drop for BusyGuard<'_> {
+    fn drop(&mut self) {
+        self.0.set(false);
+    }
+}
+
+type DeferredFree = Cell<Option<(*mut u8, Layout)>>;
+
+/// Nested deallocs, freed once the allocator is released
+pub(crate) struct DeferredFrees {
+    frees: [DeferredFree; MAX_DEFERRED_FREES],
+    len: Cell<usize>,
+}
+
+impl DeferredFrees {
+    pub(crate) const fn new() -> Self {
+        DeferredFrees {
+            frees: [const { Cell::new(None) }; MAX_DEFERRED_FREES],
+            len: Cell::new(0),
+        }
+    }
+
+    /// returns false if the list is full
+    pub(crate) fn push(&self, ptr: *mut u8, layout: Layout) -> bool {
+        let len = self.len.get();
+        if len == MAX_DEFERRED_FREES {
+            return false;
+        }
+        self.frees[len].set(Some((ptr, layout)));
+        self.len.set(len + 1);
+        true
+    }
+
+    pub(crate) fn pop(&self) -> Option<(*mut u8, Layout)> {
+        let len = self.len.get().checked_sub(1)?;
+        self.len.set(len);
+        self.frees[len].take()
+    }
+}
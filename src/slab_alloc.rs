@@ -0,0 +1,291 @@
+//! Slab allocator
+//! Segregated size classes, each class is a pool of fixed size blocks.
+
+use crate::poison::Poison;
+use crate::zeroize::zeroize;
+use core::alloc::Layout;
+
+/// Max number of size classes
+pub const MAX_SIZE_CLASSES: usize = 8;
+
+/// What to do if the size class of a request is exhausted
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SlabFallback {
+    /// Try larger size classes, then the buddy allocator
+    LargerClass,
+    /// Go to the buddy allocator directly
+    Buddy,
+    /// Fail the request
+    Fail,
+}
+
+#[derive(Clone, Copy)]
+pub struct SizeClassParam {
+    /// Block size: bytes of each block in this class
+    block_size: usize,
+    /// Base addr: the start address of the pool
+    pub(crate) base_addr: *const u8,
+    /// Len: available bytes from the start address
+    len: usize,
+}
+
+impl SizeClassParam {
+    /// Block size: bytes of each block, must be a multiple of the pointer size
+    /// Base addr: the start address of the pool, must align to the largest power of two divides block size
+    /// Len: available bytes from the start address
+    pub const fn new(block_size: usize, base_addr: *const u8, len: usize) -> Self {
+        SizeClassParam {
+            block_size,
+            base_addr,
+            len,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct SlabAllocParam {
+    /// Size classes in ascending order of block size
+    classes: &'static [SizeClassParam],
+    fallback: SlabFallback,
+    poison: Option<Poison>,
+    zeroize: bool,
+}
+
+impl SlabAllocParam {
+    /// Classes: size classes in ascending order of block size, at most `MAX_SIZE_CLASSES`
+    pub const fn new(classes: &'static [SizeClassParam]) -> Self {
+        Self::new_with_fallback(classes, SlabFallback::LargerClass)
+    }
+
+    /// Similar to new, but with a custom fallback policy
+    pub const fn new_with_fallback(
+        classes: &'static [SizeClassParam],
+        fallback: SlabFallback,
+    ) -> Self {
+        SlabAllocParam {
+            classes,
+            fallback,
+            poison: None,
+            zeroize: false,
+        }
+    }
+
+    /// Debug mode: poison blocks on alloc and free, see `Poison`
+    pub const fn with_poison(mut self, poison: Poison) -> Self {
+        self.poison = Some(poison);
+        self
+    }
+
+    /// Secure mode: wipe every freed block, so the freed memory does not keep secrets.
+    pub const fn with_zeroize(mut self) -> Self {
+        self.zeroize = true;
+        self
+    }
+}
+
+struct FreeBlock {
+    next: *mut FreeBlock,
+}
+
+const FREE_BLOCK_SIZE: usize = core::mem::size_of::<FreeBlock>();
+
+#[derive(Clone, Copy)]
+struct SizeClass {
+    block_size: usize,
+    /// alignment of blocks
+    align: usize,
+    /// memory start addr
+    base_addr: usize,
+    /// memory bytes from base_addr
+    len: usize,
+    /// offset from base_addr to allocate blocks
+    next_offset: usize,
+    free: *mut FreeBlock,
+}
+
+impl SizeClass {
+    const EMPTY: SizeClass = SizeClass {
+        block_size: 0,
+        align: 0,
+        base_addr: 0,
+        len: 0,
+        next_offset: 0,
+        free: core::ptr::null_mut(),
+    };
+
+    fn contains_ptr(&self, p: *mut u8) -> bool {
+        let addr = p as usize;
+        addr >= self.base_addr && addr - self.base_addr < self.len
+    }
+
+    fn fits(&self, layout: Layout) -> bool {
+        layout.size() <= self.block_size && layout.align() <= self.align
+    }
+}
+
+pub struct SlabAlloc {
+    classes: [SizeClass; MAX_SIZE_CLASSES],
+    nclasses: usize,
+    fallback: SlabFallback,
+    poison: Option<Poison>,
+    zeroize: bool,
+}
+
+impl SlabAlloc {
+    /// # Safety
+    ///
+    /// The memory ranges of all size classes must be allocated before using,
+    /// and must guarantee no others write to the memory ranges, otherwise behavior is undefined.
+    pub unsafe fn new(param: SlabAllocParam) -> Self {
+        let SlabAllocParam {
+            classes: class_params,
+            fallback,
+            poison,
+            zeroize,
+        } = param;
+        assert!(
+            class_params.len() <= MAX_SIZE_CLASSES,
+            "too many size classes"
+        );
+        let mut classes = [SizeClass::EMPTY; MAX_SIZE_CLASSES];
+        let mut prev_block_size = 0;
+        for (class, param) in classes.iter_mut().zip(class_params) {
+            let block_size = param.block_size;
+            assert!(
+                block_size > prev_block_size,
+                "size classes must be in ascending order"
+            );
+            assert!(
+                block_size % FREE_BLOCK_SIZE == 0,
+                "block size must be a multiple of the pointer size"
+            );
+            // the largest power of two divides block size
+            let align = block_size & block_size.wrapping_neg();
+            let base_addr = param.base_addr as usize;
+            debug_assert_eq!(base_addr & (align - 1), 0, "misalignment");
+            let len = param.len / block_size * block_size;
+            assert!(
+                len == 0 || base_addr.checked_add(len - 1).is_some(),
+                "memory range overflows the address space"
+            );
+            *class = SizeClass {
+                block_size,
+                align,
+                base_addr,
+                len,
+                next_offset: 0,
+                free: core::ptr::null_mut(),
+            };
+            prev_block_size = block_size;
+        }
+        SlabAlloc {
+            classes,
+            nclasses: class_params.len(),
+            fallback,
+            poison,
+            zeroize,
+        }
+    }
+
+    /// index of the smallest class fits the layout
+    fn class_index(&self, layout: Layout) -> Option<usize> {
+        self.classes[..self.nclasses]
+            .iter()
+            .position(|class| class.fits(layout))
+    }
+
+    pub fn fallback(&self) -> SlabFallback {
+        self.fallback
+    }
+
+    /// Returns true if there is a size class for the layout
+    pub fn serves(&self, layout: Layout) -> bool {
+        self.class_index(layout).is_some()
+    }
+
+    pub fn contains_ptr(&self, p: *mut u8) -> bool {
+        self.classes[..self.nclasses]
+            .iter()
+            .any(|class| class.contains_ptr(p))
+    }
+
+    /// Returns the block size of the class p belongs to
+    pub fn usable_size(&self, p: *mut u8) -> usize {
+        self.classes[..self.nclasses]
+            .iter()
+            .find(|class| class.contains_ptr(p))
+            .map(|class| class.block_size)
+            .expect("block not belongs to SlabAlloc")
+    }
+
+    /// Allocate from the smallest size class fits the layout,
+    /// larger classes are tried if the fallback is `SlabFallback::LargerClass`.
+    /// Returns null if no class can serve the request.
+    pub fn malloc(&mut self, layout: Layout) -> *mut u8 {
+        let first = match self.class_index(layout) {
+            Some(i) => i,
+            None => return core::ptr::null_mut(),
+        };
+        let last = if self.fallback == SlabFallback::LargerClass {
+            self.nclasses
+        } else {
+            first + 1
+        };
+        for i in first..last {
+            if !self.classes[i].fits(layout) {
+                continue;
+            }
+            let p = self.malloc_from(i);
+            if !p.is_null() {
+                return p;
+            }
+        }
+        core::ptr::null_mut()
+    }
+
+    fn malloc_from(&mut self, i: usize) -> *mut u8 {
+        let poison = self.poison;
+        let class = &mut self.classes[i];
+        let block_size = class.block_size;
+        let p = if !class.free.is_null() {
+            let p = class.free;
+            class.free = unsafe { (*p).next };
+            if let Some(poison) = poison {
+                poison.check_freed(p as *mut u8, FREE_BLOCK_SIZE, block_size);
+            }
+            p as *mut u8
+        } else if class.next_offset < class.len {
+            let p = class.base_addr + class.next_offset;
+            class.next_offset += block_size;
+            p as *mut u8
+        } else {
+            return core::ptr::null_mut();
+        };
+        if let Some(poison) = poison {
+            poison.fill_alloc(p, block_size);
+        }
+        p
+    }
+
+    /// Free a block, the layout is used to locate the size class quickly.
+    pub fn free(&mut self, p: *mut u8, layout: Layout) {
+        let i = match self.class_index(layout) {
+            Some(i) if self.classes[i].contains_ptr(p) => i,
+            // the block was served by a larger class
+            _ => self.classes[..self.nclasses]
+                .iter()
+                .position(|class| class.contains_ptr(p))
+                .expect("free a block not belongs to SlabAlloc"),
+        };
+        let class = &mut self.classes[i];
+        if self.zeroize {
+            zeroize(p, class.block_size);
+        }
+        if let Some(poison) = self.poison {
+            poison.fill_free(p, class.block_size);
+        }
+        let block = p.cast::<FreeBlock>();
+        unsafe { block.write(FreeBlock { next: class.free }) };
+        class.free = block;
+    }
+}
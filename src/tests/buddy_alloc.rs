@@ -1,4 +1,9 @@
-use crate::buddy_alloc::{block_size, BuddyAlloc, BuddyAllocParam, MIN_LEAF_SIZE_ALIGN};
+use crate::buddy_alloc::{
+    block_size, first_up_k, BuddyAlloc, BuddyAllocParam, Placement, ReserveError, Stats,
+    MAP_ALLOCATED, MAP_FREE, MAP_METADATA, MAP_SPLIT, MIN_LEAF_SIZE_ALIGN,
+};
+use crate::corruption::HeapCorruption;
+use crate::poison::{Poison, ALLOC_PATTERN, FREE_PATTERN};
 
 const HEAP_SIZE: usize = 1024 * 1024;
 const LEAF_SIZE: usize = MIN_LEAF_SIZE_ALIGN;
@@ -20,6 +25,16 @@ fn with_allocator<F: Fn(BuddyAlloc)>(heap_size: usize, leaf_size: usize, f: F) {
     }
 }
 
+const SEED: u32 = 0x2545_f491;
+
+/// xorshift, to keep random workloads reproducible
+fn xorshift(seed: &mut u32) -> u32 {
+    *seed ^= *seed << 13;
+    *seed ^= *seed >> 17;
+    *seed ^= *seed << 5;
+    *seed
+}
+
 // find a max k that less than n bytes
 pub fn first_down_k(n: usize) -> Option<usize> {
     let mut k: usize = 0;
@@ -232,3 +247,754 @@ fn test_alignment() {
     let p = allocator.malloc(4);
     println!("Allocated pointer: {:p}", p);
 }
+
+#[test]
+fn test_poison() {
+    let buf: Vec<u8> = Vec::with_capacity(HEAP_SIZE);
+    let param =
+        BuddyAllocParam::new(buf.as_ptr(), HEAP_SIZE, LEAF_SIZE).with_poison(Poison::new(true));
+    let mut allocator = unsafe { BuddyAlloc::new(param) };
+    let mut ptrs = Vec::new();
+    for &size in &[16, 64, 100, 4096] {
+        let p = allocator.malloc(size);
+        let block = unsafe { core::slice::from_raw_parts(p, size) };
+        assert!(block.iter().all(|&b| b == ALLOC_PATTERN));
+        ptrs.push((p, size));
+    }
+    for (p, size) in ptrs {
+        allocator.free(p);
+        let block = unsafe { core::slice::from_raw_parts(p, size) };
+        assert!(block[16..].iter().all(|&b| b == FREE_PATTERN));
+    }
+    // merged blocks are reusable
+    let p = allocator.malloc(HEAP_SIZE / 4);
+    assert!(!p.is_null());
+    allocator.free(p);
+}
+
+#[test]
+#[should_panic(expected = "use after free")]
+fn test_poison_detect_use_after_free() {
+    let buf: Vec<u8> = Vec::with_capacity(HEAP_SIZE);
+    let param =
+        BuddyAllocParam::new(buf.as_ptr(), HEAP_SIZE, LEAF_SIZE).with_poison(Poison::new(true));
+    let mut allocator = unsafe { BuddyAlloc::new(param) };
+    let p = allocator.malloc(64);
+    allocator.free(p);
+    unsafe { p.add(20).write(0) };
+    loop {
+        assert!(!allocator.malloc(64).is_null());
+    }
+}
+
+#[test]
+fn test_malloc_zeroed() {
+    with_allocator(HEAP_SIZE, LEAF_SIZE, |mut allocator| {
+        for _ in 0..3 {
+            let mut ptrs = Vec::new();
+            for &size in &[8, 16, 100, 4096, 64 * 1024] {
+                let p = allocator.malloc_zeroed(size);
+                assert!(!p.is_null());
+                let block = unsafe { core::slice::from_raw_parts_mut(p, size) };
+                assert!(block.iter().all(|&b| b == 0));
+                block.fill(0xFF);
+                ptrs.push(p);
+            }
+            for p in ptrs {
+                allocator.free(p);
+            }
+        }
+    });
+}
+
+#[test]
+fn test_zeroize() {
+    let buf: Vec<u8> = Vec::with_capacity(HEAP_SIZE);
+    let param = BuddyAllocParam::new(buf.as_ptr(), HEAP_SIZE, LEAF_SIZE).with_zeroize();
+    let mut allocator = unsafe { BuddyAlloc::new(param) };
+    let mut ptrs = Vec::new();
+    for &size in &[16, 100, 4096] {
+        let p = allocator.malloc(size);
+        unsafe { core::ptr::write_bytes(p, 0xFF, size) };
+        ptrs.push((p, size));
+    }
+    for (p, size) in ptrs {
+        allocator.free(p);
+        // wipe the granted block rather than the requested bytes
+        let block = unsafe { core::slice::from_raw_parts(p, size.next_power_of_two()) };
+        assert!(block.iter().skip(32).all(|&b| b == 0));
+    }
+    // wiped blocks are reused as zero filled blocks
+    for &size in &[16, 100, 4096, 64 * 1024] {
+        let p = allocator.malloc_zeroed(size);
+        let block = unsafe { core::slice::from_raw_parts_mut(p, size) };
+        assert!(block.iter().all(|&b| b == 0));
+        block.fill(0xFF);
+        allocator.free(p);
+    }
+}
+
+#[test]
+fn test_grow_in_place() {
+    with_allocator(HEAP_SIZE, LEAF_SIZE, |mut allocator| {
+        let mut ptrs = Vec::new();
+        // find a block followed by free buddies
+        let p = loop {
+            let p = allocator.malloc(100);
+            assert!(allocator.grow_in_place(p, 128));
+            if allocator.grow_in_place(p, 1000) {
+                break p;
+            }
+            // a failed growth changes nothing
+            assert_eq!(allocator.usable_size(p), 128);
+            ptrs.push(p);
+        };
+        assert_eq!(allocator.usable_size(p), 1024);
+        assert!(!allocator.grow_in_place(p, HEAP_SIZE));
+        assert_eq!(allocator.usable_size(p), 1024);
+        ptrs.push(p);
+        for p in ptrs {
+            allocator.free(p);
+        }
+        let p = allocator.malloc(HEAP_SIZE / 2);
+        assert!(!p.is_null());
+        allocator.free(p);
+    });
+}
+
+#[test]
+fn test_stats() {
+    with_allocator(HEAP_SIZE, LEAF_SIZE, |mut allocator| {
+        let stats = allocator.stats();
+        assert_eq!(stats.available_bytes, allocator.available_bytes());
+        assert!(stats.free_bytes <= stats.available_bytes);
+        assert!(stats.largest_free_block.is_power_of_two());
+        let p = allocator.malloc(stats.largest_free_block);
+        let after = allocator.stats();
+        assert_eq!(
+            after.free_bytes,
+            stats.free_bytes - stats.largest_free_block
+        );
+        assert!(after.largest_free_block <= stats.largest_free_block);
+        allocator.free(p);
+        assert_eq!(allocator.stats(), stats);
+    });
+}
+
+#[test]
+fn test_block_size_bounds() {
+    // the largest order of the pointer width
+    let leaf2base = LEAF_SIZE.trailing_zeros() as usize;
+    let k = usize::BITS as usize - 1 - leaf2base;
+    assert_eq!(block_size(k, LEAF_SIZE), 1 << (usize::BITS - 1));
+    assert_eq!(first_up_k(block_size(k, LEAF_SIZE), LEAF_SIZE), k);
+}
+
+#[test]
+#[should_panic(expected = "block size overflows usize")]
+fn test_block_size_overflow() {
+    let k = usize::BITS as usize - LEAF_SIZE.trailing_zeros() as usize;
+    block_size(k, LEAF_SIZE);
+}
+
+#[test]
+fn test_first_up_k() {
+    assert_eq!(first_up_k(0, LEAF_SIZE), 0);
+    assert_eq!(first_up_k(LEAF_SIZE, LEAF_SIZE), 0);
+    assert_eq!(first_up_k(LEAF_SIZE + 1, LEAF_SIZE), 1);
+    assert_eq!(first_up_k(4 * LEAF_SIZE, LEAF_SIZE), 2);
+    assert_eq!(first_up_k(4 * LEAF_SIZE + 1, LEAF_SIZE), 3);
+    // no overflow on huge requests
+    assert_eq!(
+        first_up_k(usize::MAX, LEAF_SIZE),
+        usize::BITS as usize - LEAF_SIZE.trailing_zeros() as usize
+    );
+}
+
+#[test]
+fn test_malloc_huge() {
+    with_allocator(HEAP_SIZE, LEAF_SIZE, |mut allocator| {
+        assert!(allocator.malloc(usize::MAX).is_null());
+        assert!(allocator.malloc(usize::MAX / 2 + 2).is_null());
+        let p = allocator.malloc(16);
+        assert!(!allocator.grow_in_place(p, usize::MAX));
+    });
+}
+
+#[test]
+#[should_panic(expected = "memory range overflows the address space")]
+fn test_range_overflow() {
+    let param = BuddyAllocParam::new(usize::MAX as *const u8, 2 * LEAF_SIZE, LEAF_SIZE);
+    unsafe { BuddyAlloc::new(param) };
+}
+
+#[test]
+fn test_random_malloc_and_free() {
+    with_allocator(HEAP_SIZE, LEAF_SIZE, |mut allocator| {
+        let mut seed = SEED;
+        let mut rand = move || xorshift(&mut seed) as usize;
+        let available = allocator.stats();
+        let mut ptrs = Vec::new();
+        for _ in 0..10_000 {
+            if ptrs.is_empty() || rand() % 3 != 0 {
+                let size = 1 + rand() % 4096;
+                let p = allocator.malloc(size);
+                if !p.is_null() {
+                    let k = first_up_k(size, LEAF_SIZE);
+                    assert_eq!(allocator.usable_size(p), block_size(k, LEAF_SIZE));
+                    ptrs.push(p);
+                }
+            } else {
+                let p = ptrs.swap_remove(rand() % ptrs.len());
+                allocator.free(p);
+            }
+        }
+        assert_eq!(allocator.check_invariants(), Ok(()));
+        for p in ptrs {
+            allocator.free(p);
+        }
+        assert_eq!(allocator.stats(), available);
+        assert_eq!(allocator.check_invariants(), Ok(()));
+    });
+}
+
+/// runs rounds of randomized short-lived blocks with a few long-lived blocks allocated in
+/// the middle of each round, returns the stats with the long-lived blocks allocated.
+fn fragmentation_workload(placement: Placement) -> Stats {
+    let buf: Vec<u8> = Vec::with_capacity(HEAP_SIZE);
+    let param = BuddyAllocParam::new(buf.as_ptr(), HEAP_SIZE, LEAF_SIZE).with_placement(placement);
+    let mut allocator = unsafe { BuddyAlloc::new(param) };
+    let mut seed = SEED;
+    let mut rand = move || xorshift(&mut seed) as usize;
+    for _round in 0..50 {
+        let mut short_lived: Vec<*mut u8> = (0..1024)
+            .map(|_| allocator.malloc(1 + rand() % 512))
+            .collect();
+        for _ in 0..512 {
+            let p = short_lived.swap_remove(rand() % short_lived.len());
+            allocator.free(p);
+        }
+        // long-lived blocks fill the holes left by the short-lived blocks
+        for _ in 0..16 {
+            assert!(!allocator.malloc(1 + rand() % 512).is_null());
+        }
+        for p in short_lived {
+            allocator.free(p);
+        }
+    }
+    allocator.stats()
+}
+
+#[test]
+fn test_placement_fragmentation() {
+    let lifo = fragmentation_workload(Placement::Lifo);
+    let lowest = fragmentation_workload(Placement::LowestAddress);
+    let highest = fragmentation_workload(Placement::HighestAddress);
+    // same blocks are allocated, but packed together by the address-ordered placements
+    assert_eq!(lowest.free_bytes, lifo.free_bytes);
+    assert_eq!(highest.free_bytes, lifo.free_bytes);
+    assert!(lowest.free_blocks < lifo.free_blocks / 2);
+    assert!(highest.free_blocks < lifo.free_blocks / 2);
+    // the largest block is at the start of the heap, packing blocks at the end keeps it
+    assert!(highest.largest_free_block > lifo.largest_free_block);
+}
+
+#[test]
+fn test_alloc_exact() {
+    let buf: Vec<u8> = Vec::with_capacity(HEAP_SIZE);
+    let param = BuddyAllocParam::new(buf.as_ptr(), HEAP_SIZE, LEAF_SIZE).with_exact_alloc();
+    let mut allocator = unsafe { BuddyAlloc::new(param) };
+    let initial = allocator.stats();
+    let p = allocator.alloc_exact(33 * 1024 + 1);
+    assert!(!p.is_null());
+    // 33 KB and a leaf instead of a 64 KB block
+    assert_eq!(allocator.usable_size(p), 33 * 1024 + LEAF_SIZE);
+    assert_eq!(
+        allocator.stats().free_bytes,
+        initial.free_bytes - 33 * 1024 - LEAF_SIZE
+    );
+    unsafe { core::ptr::write_bytes(p, 0xff, 33 * 1024 + 1) };
+    // the neighbours of the pieces are still usable
+    let q = allocator.malloc(LEAF_SIZE);
+    allocator.free(p);
+    allocator.free(q);
+    assert_eq!(allocator.stats(), initial);
+
+    // without extent bits it is a plain malloc
+    let mut allocator =
+        unsafe { BuddyAlloc::new(BuddyAllocParam::new(buf.as_ptr(), HEAP_SIZE, LEAF_SIZE)) };
+    let p = allocator.alloc_exact(33 * 1024);
+    assert_eq!(allocator.usable_size(p), 64 * 1024);
+}
+
+#[test]
+fn test_random_alloc_exact_and_free() {
+    let buf: Vec<u8> = Vec::with_capacity(HEAP_SIZE);
+    let param = BuddyAllocParam::new(buf.as_ptr(), HEAP_SIZE, LEAF_SIZE)
+        .with_exact_alloc()
+        .with_poison(Poison::new(true));
+    let mut allocator = unsafe { BuddyAlloc::new(param) };
+    let initial = allocator.stats();
+    let mut seed = SEED;
+    let mut rand = move || xorshift(&mut seed) as usize;
+    let mut ptrs = Vec::new();
+    for _ in 0..10_000 {
+        if ptrs.is_empty() || rand() % 3 != 0 {
+            let size = 1 + rand() % 8192;
+            let p = if rand() % 2 == 0 {
+                allocator.alloc_exact(size)
+            } else {
+                allocator.malloc(size)
+            };
+            if !p.is_null() {
+                assert!(allocator.usable_size(p) >= size);
+                unsafe { core::ptr::write_bytes(p, 0x42, size) };
+                ptrs.push(p);
+            }
+        } else {
+            let p = ptrs.swap_remove(rand() % ptrs.len());
+            allocator.free(p);
+        }
+    }
+    assert_eq!(allocator.check_invariants(), Ok(()));
+    for p in ptrs {
+        allocator.free(p);
+    }
+    assert_eq!(allocator.stats(), initial);
+    assert_eq!(allocator.check_invariants(), Ok(()));
+}
+
+#[test]
+fn test_shrink() {
+    with_allocator(HEAP_SIZE, LEAF_SIZE, |mut allocator| {
+        let initial = allocator.stats();
+        let p = allocator.malloc(64 * 1024);
+        unsafe { core::ptr::write_bytes(p, 0x42, 64 * 1024) };
+        allocator.shrink(p, 100);
+        assert_eq!(allocator.usable_size(p), 128);
+        assert_eq!(allocator.stats().free_bytes, initial.free_bytes - 128);
+        assert!(unsafe { core::slice::from_raw_parts(p, 100) }
+            .iter()
+            .all(|&b| b == 0x42));
+        // larger sizes are ignored
+        allocator.shrink(p, 1024);
+        assert_eq!(allocator.usable_size(p), 128);
+        allocator.free(p);
+        assert_eq!(allocator.stats(), initial);
+    });
+}
+
+#[test]
+fn test_shrink_exact() {
+    let buf: Vec<u8> = Vec::with_capacity(HEAP_SIZE);
+    let param = BuddyAllocParam::new(buf.as_ptr(), HEAP_SIZE, LEAF_SIZE)
+        .with_exact_alloc()
+        .with_poison(Poison::new(true));
+    let mut allocator = unsafe { BuddyAlloc::new(param) };
+    let initial = allocator.stats();
+    // pieces of an exact block are split or freed
+    let p = allocator.alloc_exact(33 * 1024 + 1);
+    allocator.shrink(p, 5 * 1024 + 1);
+    assert_eq!(allocator.usable_size(p), 5 * 1024 + LEAF_SIZE);
+    assert_eq!(
+        allocator.stats().free_bytes,
+        initial.free_bytes - 5 * 1024 - LEAF_SIZE
+    );
+    // a power of two block shrinks to an exact block
+    let q = allocator.malloc(8 * 1024);
+    allocator.shrink(q, 3 * 1024 - 1);
+    assert_eq!(allocator.usable_size(q), 3 * 1024);
+    allocator.free(p);
+    allocator.free(q);
+    assert_eq!(allocator.stats(), initial);
+}
+
+#[test]
+fn test_alloc_order() {
+    with_allocator(HEAP_SIZE, LEAF_SIZE, |mut allocator| {
+        let initial = allocator.stats();
+        let mut blocks = Vec::new();
+        for order in 0..8 {
+            let p = allocator.alloc_order(order).unwrap();
+            assert_eq!(
+                allocator.usable_size(p.as_ptr()),
+                block_size(order, LEAF_SIZE)
+            );
+            blocks.push((p, order));
+        }
+        // out of the heap
+        assert!(allocator.alloc_order(usize::BITS as usize).is_none());
+        for (p, order) in blocks {
+            allocator.free_order(p, order);
+        }
+        assert_eq!(allocator.stats(), initial);
+    });
+}
+
+#[test]
+fn test_frame_number() {
+    with_allocator(HEAP_SIZE, LEAF_SIZE, |mut allocator| {
+        assert_eq!(allocator.frame_size(), LEAF_SIZE);
+        let p = allocator.alloc_order(2).unwrap().as_ptr();
+        let frame = allocator.frame_number(p);
+        assert_eq!(frame * LEAF_SIZE, p as usize);
+        assert_eq!(allocator.frame_ptr(frame + 3), unsafe {
+            p.add(3 * LEAF_SIZE)
+        });
+    });
+}
+
+#[test]
+fn test_alloc_contiguous() {
+    with_allocator(HEAP_SIZE, LEAF_SIZE, |mut allocator| {
+        let p = allocator.alloc_contiguous(3).unwrap();
+        assert_eq!(allocator.usable_size(p.as_ptr()), 4 * LEAF_SIZE);
+        allocator.free(p.as_ptr());
+    });
+    let buf: Vec<u8> = Vec::with_capacity(HEAP_SIZE);
+    let param = BuddyAllocParam::new(buf.as_ptr(), HEAP_SIZE, LEAF_SIZE).with_exact_alloc();
+    let mut allocator = unsafe { BuddyAlloc::new(param) };
+    let initial = allocator.stats();
+    let p = allocator.alloc_contiguous(3).unwrap();
+    assert_eq!(allocator.usable_size(p.as_ptr()), 3 * LEAF_SIZE);
+    allocator.free(p.as_ptr());
+    assert_eq!(allocator.stats(), initial);
+}
+
+#[test]
+fn test_reserve() {
+    with_allocator(HEAP_SIZE, LEAF_SIZE, |mut allocator| {
+        let initial = allocator.stats();
+        let p = allocator.malloc(64 * 1024);
+        allocator.free(p);
+        // an unaligned range in the middle of a free block
+        let start = p as usize + 3 * LEAF_SIZE + 1;
+        let end = start + 5 * 1024;
+        assert_eq!(allocator.reserve(start as *const u8, end - start), Ok(()));
+        // exactly the covering leaves are taken
+        let covering = (end - 1) / LEAF_SIZE - start / LEAF_SIZE + 1;
+        assert_eq!(
+            allocator.stats().free_bytes,
+            initial.free_bytes - covering * LEAF_SIZE
+        );
+        let mut leaves = Vec::new();
+        loop {
+            let q = allocator.malloc(LEAF_SIZE);
+            if q.is_null() {
+                break;
+            }
+            assert!(q as usize + LEAF_SIZE <= start || q as usize >= end);
+            leaves.push(q);
+        }
+        for q in leaves {
+            allocator.free(q);
+        }
+        allocator.release_reserved(start as *const u8, end - start);
+        assert_eq!(allocator.stats(), initial);
+    });
+}
+
+#[test]
+fn test_reserve_fail() {
+    with_allocator(HEAP_SIZE, LEAF_SIZE, |mut allocator| {
+        let p = allocator.malloc(1024);
+        let stats = allocator.stats();
+        assert_eq!(
+            allocator.reserve(unsafe { p.add(1000) }, 1),
+            Err(ReserveError::InUse)
+        );
+        assert_eq!(
+            allocator.reserve(core::ptr::null(), 1),
+            Err(ReserveError::OutOfHeap)
+        );
+        assert_eq!(
+            allocator.reserve(p, HEAP_SIZE),
+            Err(ReserveError::OutOfHeap)
+        );
+        // nothing is changed
+        assert_eq!(allocator.stats(), stats);
+        allocator.free(p);
+    });
+}
+
+#[test]
+fn test_holes() {
+    let mut buf: Vec<u8> = vec![0; HEAP_SIZE];
+    let base = buf.as_ptr() as usize;
+    // a hole at the start, where the metadata would be, and unaligned holes in the middle
+    let holes = [
+        base..(base + 100),
+        (base + 300 * 1024 + 7)..(base + 301 * 1024),
+        (base + 700 * 1024)..(base + 900 * 1024 + 3),
+    ];
+    for hole in &holes {
+        buf[(hole.start - base)..(hole.end - base)].fill(0xcc);
+    }
+    let param =
+        BuddyAllocParam::new(buf.as_ptr(), HEAP_SIZE, LEAF_SIZE).with_poison(Poison::new(true));
+    let mut allocator = unsafe { BuddyAlloc::new_with_holes(param, &holes) };
+    let initial = allocator.stats();
+    assert!(initial.available_bytes < HEAP_SIZE - 200 * 1024 - 100);
+    // the metadata is inside the heap
+    let mut map = String::new();
+    allocator.write_map(&mut map, 4096).unwrap();
+    assert!(map.contains(MAP_METADATA));
+    assert_eq!(initial.free_bytes, initial.available_bytes);
+    let mut leaves = Vec::new();
+    loop {
+        let p = allocator.malloc(LEAF_SIZE);
+        if p.is_null() {
+            break;
+        }
+        let p = p as usize;
+        assert!(holes
+            .iter()
+            .all(|hole| p + LEAF_SIZE <= hole.start || p >= hole.end));
+        leaves.push(p as *mut u8);
+    }
+    assert_eq!(leaves.len() * LEAF_SIZE, initial.available_bytes);
+    assert_eq!(allocator.check_invariants(), Ok(()));
+    for p in leaves {
+        allocator.free(p);
+    }
+    assert_eq!(allocator.stats(), initial);
+    // holes are never written
+    for hole in &holes {
+        assert!(buf[(hole.start - base)..(hole.end - base)]
+            .iter()
+            .all(|&b| b == 0xcc));
+    }
+}
+
+#[test]
+fn test_holes_reserve() {
+    let buf: Vec<u8> = Vec::with_capacity(HEAP_SIZE);
+    let base = buf.as_ptr() as usize;
+    let hole = (base + 512 * 1024)..(base + 516 * 1024);
+    let param = BuddyAllocParam::new(buf.as_ptr(), HEAP_SIZE, LEAF_SIZE);
+    let mut allocator = unsafe { BuddyAlloc::new_with_holes(param, core::slice::from_ref(&hole)) };
+    // the leaves of holes are in use
+    assert_eq!(
+        allocator.reserve((base + 516 * 1024 - 1) as *const u8, 1024),
+        Err(ReserveError::InUse)
+    );
+    assert_eq!(
+        allocator.reserve((base + 516 * 1024) as *const u8, 1024),
+        Ok(())
+    );
+}
+
+#[test]
+fn test_check_invariants() {
+    with_allocator(HEAP_SIZE, LEAF_SIZE, |mut allocator| {
+        assert_eq!(allocator.check_invariants(), Ok(()));
+        let p = allocator.malloc(LEAF_SIZE);
+        assert!(!allocator.malloc(LEAF_SIZE).is_null());
+        assert_eq!(allocator.check_invariants(), Ok(()));
+        // p is a free leaf and its buddy is allocated
+        allocator.free(p);
+        assert_eq!(allocator.check_invariants(), Ok(()));
+        // overwrite the links of the free block
+        let bad = p as usize + 1;
+        unsafe { core::ptr::write_unaligned(p.cast::<[usize; 2]>(), [bad, bad]) };
+        assert_eq!(
+            allocator.check_invariants(),
+            Err(HeapCorruption::BrokenLink {
+                order: 0,
+                addr: p as usize
+            })
+        );
+    });
+}
+
+#[test]
+fn test_write_map() {
+    with_allocator(64 * 1024, LEAF_SIZE, |mut allocator| {
+        // returns the address of the first cell and the cells
+        let render = |allocator: &BuddyAlloc| {
+            let mut map = String::new();
+            allocator.write_map(&mut map, 1024).unwrap();
+            let start = map.split(' ').next().unwrap().trim_start_matches("0x");
+            let start = usize::from_str_radix(start, 16).unwrap();
+            let cells: Vec<char> = map
+                .lines()
+                .flat_map(|line| line.split(' ').nth(1).unwrap().chars())
+                .collect();
+            (start, cells)
+        };
+        let (start, initial) = render(&allocator);
+        // the metadata is in front of the heap
+        assert_eq!(initial[0], MAP_METADATA);
+        assert!(initial.contains(&MAP_FREE));
+        let p = allocator.malloc(1024);
+        let q = allocator.malloc(LEAF_SIZE);
+        let (_, cells) = render(&allocator);
+        assert_eq!(cells.len(), initial.len());
+        assert_eq!(cells[(p as usize - start) / 1024], MAP_ALLOCATED);
+        assert_eq!(cells[(q as usize - start) / 1024], MAP_SPLIT);
+        allocator.free(p);
+        allocator.free(q);
+        assert_eq!(render(&allocator), (start, initial));
+    });
+}
+
+#[cfg(feature = "json-map")]
+#[test]
+fn test_write_json_map() {
+    with_allocator(64 * 1024, LEAF_SIZE, |mut allocator| {
+        let p = allocator.malloc(1024);
+        let mut json = String::new();
+        allocator.write_json_map(&mut json).unwrap();
+        let mut lines = json.lines();
+        assert!(lines.next().unwrap().ends_with(r#""state":"metadata"}"#));
+        let block = format!(
+            r#"{{"addr":{},"order":6,"size":1024,"state":"allocated"}}"#,
+            p as usize
+        );
+        assert!(lines.any(|line| line == block));
+        // blocks not split cover the heap
+        let free_bytes: usize = json
+            .lines()
+            .filter(|line| line.ends_with(r#""state":"free"}"#))
+            .map(|line| {
+                let size = line.split(r#""size":"#).nth(1).unwrap();
+                size.split(',').next().unwrap().parse::<usize>().unwrap()
+            })
+            .sum();
+        assert_eq!(free_bytes, allocator.stats().free_bytes);
+    });
+}
+
+/// fills a heap with leaves, corrupts it with the first leaf,
+/// returns the first leaf and the result of the check
+fn check_corrupted_heap<F: FnOnce(&mut BuddyAlloc, *mut u8)>(
+    corrupt: F,
+) -> (usize, Result<(), HeapCorruption>) {
+    let buf: Vec<u8> = Vec::with_capacity(64 * 1024);
+    let param = BuddyAllocParam::new(buf.as_ptr(), 64 * 1024, LEAF_SIZE);
+    let mut allocator = unsafe { BuddyAlloc::new(param) };
+    let mut base = usize::MAX;
+    loop {
+        let p = allocator.malloc(LEAF_SIZE);
+        if p.is_null() {
+            break;
+        }
+        base = base.min(p as usize);
+    }
+    assert_eq!(allocator.check_invariants(), Ok(()));
+    // the buddy of the first leaf is the second leaf, their parent's buddy is allocated
+    corrupt(&mut allocator, base as *mut u8);
+    (base, allocator.check_invariants())
+}
+
+#[test]
+fn test_check_invariants_bitmaps() {
+    let (addr, result) = check_corrupted_heap(|allocator, p| {
+        allocator.free(p);
+        allocator.toggle_alloc_bit(0, p);
+    });
+    assert_eq!(
+        result,
+        Err(HeapCorruption::FreeBlockAllocated { order: 0, addr })
+    );
+    let (addr, result) = check_corrupted_heap(|allocator, p| {
+        allocator.free(p);
+        allocator.toggle_split_bit(1, p);
+    });
+    assert_eq!(
+        result,
+        Err(HeapCorruption::ParentNotSplit { order: 0, addr })
+    );
+    let (addr, result) = check_corrupted_heap(|allocator, p| {
+        allocator.toggle_alloc_bit(1, p);
+    });
+    assert_eq!(
+        result,
+        Err(HeapCorruption::SplitNotAllocated { order: 1, addr })
+    );
+    let (addr, result) = check_corrupted_heap(|allocator, p| {
+        // the leaves merge to a free order 1 block
+        allocator.free(p);
+        allocator.free(unsafe { p.add(LEAF_SIZE) });
+        allocator.toggle_alloc_bit(0, p);
+    });
+    assert_eq!(
+        result,
+        Err(HeapCorruption::StaleChildBits { order: 1, addr })
+    );
+    let (_, result) = check_corrupted_heap(|allocator, p| {
+        // an allocated leaf looks free, but it's not on the free list
+        allocator.toggle_alloc_bit(0, p);
+    });
+    assert_eq!(
+        result,
+        Err(HeapCorruption::ByteCountMismatch {
+            listed_bytes: 0,
+            free_bytes: LEAF_SIZE
+        })
+    );
+}
+
+#[test]
+#[should_panic(expected = "memory range overflows the address space")]
+fn test_region_overflow() {
+    // checked before the metadata is written
+    let base = 0usize.wrapping_sub(HEAP_SIZE);
+    let param = BuddyAllocParam::new(base as *const u8, HEAP_SIZE + LEAF_SIZE, LEAF_SIZE);
+    unsafe { BuddyAlloc::new(param) };
+}
+
+#[test]
+fn test_heap_end() {
+    // the top of the address space can't be mapped here, the len boundary paths are the same
+    // at any heap end, which is after a leaf or in the middle of one
+    let buf: Vec<u8> = Vec::with_capacity(HEAP_SIZE + 4 * LEAF_SIZE);
+    let start = buf.as_ptr() as usize;
+    let last_end = (start + HEAP_SIZE + 3 * LEAF_SIZE) & !(LEAF_SIZE - 1);
+    let last = (last_end - LEAF_SIZE) as *const u8;
+    for tail in [0, 7] {
+        let end = last_end + tail;
+        let param = BuddyAllocParam::new(buf.as_ptr(), end - start, LEAF_SIZE).with_exact_alloc();
+        let mut allocator = unsafe { BuddyAlloc::new(param) };
+        let initial = allocator.stats();
+        assert_eq!(allocator.check_invariants(), Ok(()));
+
+        // ranges up to the end are in the heap, the partial leaf is in use
+        assert_eq!(
+            allocator.reserve(last, end - last as usize + 1),
+            Err(ReserveError::OutOfHeap)
+        );
+        if tail == 0 {
+            assert_eq!(allocator.reserve(last, end - last as usize), Ok(()));
+        } else {
+            assert_eq!(
+                allocator.reserve(last, end - last as usize),
+                Err(ReserveError::InUse)
+            );
+            assert_eq!(allocator.reserve(last, LEAF_SIZE), Ok(()));
+        }
+        allocator.release_reserved(last, LEAF_SIZE);
+        assert_eq!(allocator.stats(), initial);
+
+        // every usable leaf is allocated, the last one ends at last_end
+        let mut leaves = Vec::new();
+        loop {
+            let p = allocator.malloc(LEAF_SIZE);
+            if p.is_null() {
+                break;
+            }
+            leaves.push(p);
+        }
+        assert_eq!(leaves.len() * LEAF_SIZE, initial.available_bytes);
+        assert_eq!(
+            leaves.iter().map(|&p| p as usize + LEAF_SIZE).max(),
+            Some(last_end)
+        );
+        // no piece follows the block at the end
+        for p in leaves.into_iter().rev() {
+            allocator.free(p);
+        }
+        assert_eq!(allocator.stats(), initial);
+        assert_eq!(allocator.check_invariants(), Ok(()));
+    }
+}
@@ -0,0 +1,100 @@
+use crate::buddy_alloc::{BuddyAlloc, BuddyAllocParam};
+use crate::chunked_fast_alloc::{ChunkedFastAlloc, ChunkedFastAllocParam, DEFAULT_CHUNK_SIZE};
+use crate::fast_alloc::BLOCK_SIZE;
+
+const HEAP_SIZE: usize = 256 * 1024;
+const LEAF_SIZE: usize = 16;
+
+fn with_allocator<F: FnOnce(ChunkedFastAlloc, BuddyAlloc)>(f: F) {
+    let buf: Vec<u8> = Vec::with_capacity(HEAP_SIZE);
+    let buddy =
+        unsafe { BuddyAlloc::new(BuddyAllocParam::new(buf.as_ptr(), HEAP_SIZE, LEAF_SIZE)) };
+    f(
+        ChunkedFastAlloc::new(ChunkedFastAllocParam::default()),
+        buddy,
+    );
+}
+
+#[test]
+fn test_malloc_and_free() {
+    with_allocator(|mut allocator, mut buddy| {
+        let blocks_per_chunk = DEFAULT_CHUNK_SIZE / BLOCK_SIZE - 1;
+        for _ in 0..3 {
+            let mut ptrs = Vec::new();
+            // fill several chunks
+            for _ in 0..blocks_per_chunk * 3 + 1 {
+                let p = allocator.malloc(BLOCK_SIZE, &mut buddy);
+                assert!(!p.is_null());
+                assert!(buddy.contains_ptr(p));
+                assert!(allocator.contains_ptr(p, &buddy));
+                unsafe { core::ptr::write_bytes(p, 0xFF, BLOCK_SIZE) };
+                ptrs.push(p);
+            }
+            assert!(allocator.malloc(BLOCK_SIZE + 1, &mut buddy).is_null());
+            // free in an interleaved order
+            let (odd, even): (Vec<_>, Vec<_>) =
+                ptrs.iter().enumerate().partition(|(i, _)| i & 1 == 1);
+            for (_, &p) in odd.into_iter().chain(even) {
+                allocator.free(p, &mut buddy);
+            }
+            // all chunks are given back
+            let p = buddy.malloc(HEAP_SIZE / 2);
+            assert!(!p.is_null());
+            buddy.free(p);
+        }
+    });
+}
+
+#[test]
+fn test_contains_ptr() {
+    with_allocator(|mut allocator, mut buddy| {
+        let chunk_block = allocator.malloc(8, &mut buddy);
+        for &size in &[16, 64, DEFAULT_CHUNK_SIZE, DEFAULT_CHUNK_SIZE * 2] {
+            let p = buddy.malloc(size);
+            assert!(!allocator.contains_ptr(p, &buddy));
+            buddy.free(p);
+        }
+        assert!(allocator.contains_ptr(chunk_block, &buddy));
+        allocator.free(chunk_block, &mut buddy);
+        assert!(!allocator.contains_ptr(chunk_block, &buddy));
+    });
+}
+
+#[test]
+fn test_out_of_memory() {
+    with_allocator(|mut allocator, mut buddy| {
+        let mut ptrs = Vec::new();
+        loop {
+            let p = allocator.malloc(BLOCK_SIZE, &mut buddy);
+            if p.is_null() {
+                break;
+            }
+            ptrs.push(p);
+        }
+        assert!(ptrs.len() > HEAP_SIZE / BLOCK_SIZE / 2);
+        for p in ptrs {
+            allocator.free(p, &mut buddy);
+        }
+    });
+}
+
+#[test]
+fn test_leaf_sized_chunk() {
+    // chunks are leaves of the buddy heap
+    let buf: Vec<u8> = Vec::with_capacity(HEAP_SIZE);
+    let param = BuddyAllocParam::new(buf.as_ptr(), HEAP_SIZE, DEFAULT_CHUNK_SIZE);
+    let mut buddy = unsafe { BuddyAlloc::new(param) };
+    let mut allocator = ChunkedFastAlloc::new(ChunkedFastAllocParam::default());
+    let initial = buddy.stats();
+    let p = allocator.malloc(BLOCK_SIZE, &mut buddy);
+    let q = allocator.malloc(BLOCK_SIZE, &mut buddy);
+    assert!(allocator.contains_ptr(p, &buddy));
+    allocator.free(p, &mut buddy);
+    // the chunk is kept for q
+    assert_eq!(
+        buddy.stats().free_bytes,
+        initial.free_bytes - DEFAULT_CHUNK_SIZE
+    );
+    allocator.free(q, &mut buddy);
+    assert_eq!(buddy.stats(), initial);
+}
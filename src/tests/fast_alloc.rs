@@ -1,4 +1,6 @@
-use crate::fast_alloc::{FastAlloc, FastAllocParam, BLOCK_SIZE};
+use crate::corruption::HeapCorruption;
+use crate::fast_alloc::{FastAlloc, FastAllocParam, GenericFastAlloc, BLOCK_SIZE};
+use crate::poison::{Poison, ALLOC_PATTERN, FREE_PATTERN};
 
 #[repr(align(64))]
 struct AlignedBuf([u8; 4096]);
@@ -132,3 +134,146 @@ fn test_free_bug() {
         &buf.0,
     );
 }
+
+#[test]
+fn test_poison() {
+    let buf = AlignedBuf::default();
+    let mut allocator = unsafe {
+        let param = FastAllocParam::new(buf.0.as_ptr(), buf.0.len()).with_poison(Poison::new(true));
+        FastAlloc::new(param)
+    };
+    let p = allocator.malloc(32);
+    let block = unsafe { core::slice::from_raw_parts(p, BLOCK_SIZE) };
+    assert!(block.iter().all(|&b| b == ALLOC_PATTERN));
+    allocator.free(p);
+    let block = unsafe { core::slice::from_raw_parts(p, BLOCK_SIZE) };
+    assert!(block[16..].iter().all(|&b| b == FREE_PATTERN));
+    assert_eq!(allocator.malloc(32), p);
+}
+
+#[test]
+#[should_panic(expected = "use after free")]
+fn test_poison_detect_use_after_free() {
+    let buf = AlignedBuf::default();
+    let mut allocator = unsafe {
+        let param = FastAllocParam::new(buf.0.as_ptr(), buf.0.len()).with_poison(Poison::new(true));
+        FastAlloc::new(param)
+    };
+    let p = allocator.malloc(32);
+    allocator.free(p);
+    unsafe { p.add(40).write(0) };
+    allocator.malloc(32);
+}
+
+#[test]
+fn test_poison_lazily() {
+    let buf = AlignedBuf::default();
+    let mut allocator = unsafe {
+        let param = FastAllocParam::new_with_initialized_nodes(buf.0.as_ptr(), buf.0.len(), 2)
+            .with_poison(Poison::new(true));
+        FastAlloc::new(param)
+    };
+    // only the initialized nodes are poisoned
+    assert!(buf.0[(2 * BLOCK_SIZE)..].iter().all(|&b| b == 0));
+    let ptrs: Vec<_> = (0..3).map(|_| allocator.malloc(32)).collect();
+    for &p in &ptrs {
+        let block = unsafe { core::slice::from_raw_parts(p, BLOCK_SIZE) };
+        assert!(block.iter().all(|&b| b == ALLOC_PATTERN));
+    }
+    assert!(buf.0[(3 * BLOCK_SIZE)..].iter().all(|&b| b == 0));
+    for p in ptrs {
+        allocator.free(p);
+    }
+    assert!(!allocator.malloc(32).is_null());
+}
+
+#[test]
+fn test_zeroize() {
+    let buf = AlignedBuf::default();
+    let mut allocator = unsafe {
+        let param = FastAllocParam::new(buf.0.as_ptr(), buf.0.len()).with_zeroize();
+        FastAlloc::new(param)
+    };
+    let p = allocator.malloc(8);
+    unsafe { core::ptr::write_bytes(p, 0xFF, BLOCK_SIZE) };
+    allocator.free(p);
+    let block = unsafe { core::slice::from_raw_parts(p, BLOCK_SIZE) };
+    assert!(block[16..].iter().all(|&b| b == 0));
+}
+
+#[test]
+fn test_custom_block_size() {
+    fn _test_block_size<const BLOCK: usize>() {
+        let buf = AlignedBuf::default();
+        let mut allocator = unsafe {
+            GenericFastAlloc::<BLOCK>::new(FastAllocParam::new(buf.0.as_ptr(), buf.0.len()))
+        };
+        assert!(allocator.malloc(BLOCK + 1).is_null());
+        let mut ptrs = Vec::new();
+        for _ in 0..buf.0.len() / BLOCK {
+            let p = allocator.malloc(BLOCK);
+            assert!(!p.is_null());
+            assert_eq!(p as usize % BLOCK, 0);
+            ptrs.push(p);
+        }
+        assert!(allocator.malloc(1).is_null());
+        for p in ptrs {
+            allocator.free(p);
+        }
+        assert!(!allocator.malloc(BLOCK).is_null());
+    }
+    _test_block_size::<16>();
+    _test_block_size::<32>();
+    _test_block_size::<64>();
+}
+
+#[test]
+fn test_check_invariants() {
+    let buf = AlignedBuf::default();
+    with_allocator(
+        |mut allocator| {
+            assert_eq!(allocator.check_invariants(), Ok(()));
+            let ptrs: Vec<*mut u8> = (0..8).map(|_| allocator.malloc(BLOCK_SIZE)).collect();
+            for &p in ptrs.iter().step_by(2) {
+                allocator.free(p);
+            }
+            assert_eq!(allocator.check_invariants(), Ok(()));
+            // overwrite the links of a free block
+            let p = ptrs[6];
+            let bad = ptrs[7] as usize;
+            unsafe { core::ptr::write_unaligned(p.cast::<[usize; 2]>(), [bad, bad]) };
+            assert!(matches!(
+                allocator.check_invariants(),
+                Err(HeapCorruption::BrokenLink { order: 0, .. })
+            ));
+        },
+        &buf.0,
+    );
+}
+
+#[test]
+fn test_region_at_top_of_address_space() {
+    // blocks are never touched without initialized nodes, poison and free
+    let len = 4 * BLOCK_SIZE;
+    let base = 0usize.wrapping_sub(len);
+    let param = FastAllocParam::new_with_initialized_nodes(base as *const u8, len, 0);
+    let mut allocator = unsafe { FastAlloc::new(param) };
+    let ptrs: Vec<_> = (0..4).map(|_| allocator.malloc(BLOCK_SIZE)).collect();
+    assert_eq!(ptrs[0] as usize, base);
+    assert_eq!(
+        *ptrs.last().unwrap() as usize,
+        usize::MAX - (BLOCK_SIZE - 1)
+    );
+    assert!(ptrs.iter().all(|&p| allocator.contains_ptr(p)));
+    assert!(!allocator.contains_ptr((base - 1) as *mut u8));
+    // exhausted without overflowing
+    assert!(allocator.malloc(BLOCK_SIZE).is_null());
+}
+
+#[test]
+#[should_panic(expected = "memory range overflows the address space")]
+fn test_region_overflow() {
+    let base = 0usize.wrapping_sub(BLOCK_SIZE);
+    let param = FastAllocParam::new_with_initialized_nodes(base as *const u8, 2 * BLOCK_SIZE, 0);
+    unsafe { FastAlloc::new(param) };
+}
@@ -0,0 +1,105 @@
+use crate::buddy_alloc::{BuddyAllocParam, MIN_LEAF_SIZE_ALIGN};
+use crate::handle_alloc::{Handle, HandleAlloc, HandleAllocParam};
+
+const HEAP_SIZE: usize = 64 * 1024;
+const LEAF_SIZE: usize = MIN_LEAF_SIZE_ALIGN;
+const MAX_HANDLES: usize = 128;
+
+fn with_allocator<F: FnOnce(HandleAlloc)>(f: F) {
+    let buf: Vec<u8> = Vec::with_capacity(HEAP_SIZE);
+    let param = BuddyAllocParam::new(buf.as_ptr(), HEAP_SIZE, LEAF_SIZE);
+    let allocator = unsafe { HandleAlloc::new(HandleAllocParam::new(param, MAX_HANDLES)) };
+    f(allocator);
+}
+
+fn fill(allocator: &HandleAlloc, handle: Handle, byte: u8) {
+    allocator.lock(handle).unwrap().fill(byte);
+}
+
+fn is_filled(allocator: &HandleAlloc, handle: Handle, byte: u8) -> bool {
+    allocator.lock(handle).unwrap().iter().all(|&b| b == byte)
+}
+
+#[test]
+fn test_lock() {
+    with_allocator(|mut allocator| {
+        let h = allocator.malloc(100).unwrap();
+        assert_eq!(allocator.size(h), 100);
+        let mut lock = allocator.lock(h).unwrap();
+        assert_eq!(lock.len(), 100);
+        lock[99] = 42;
+        // a locked block can't be locked again
+        assert!(allocator.lock(h).is_none());
+        drop(lock);
+        assert_eq!(allocator.lock(h).unwrap()[99], 42);
+        allocator.free(h);
+    });
+}
+
+#[test]
+fn test_handles_exhausted() {
+    with_allocator(|mut allocator| {
+        let handles: Vec<Handle> = (0..MAX_HANDLES)
+            .map(|_| allocator.malloc(16).unwrap())
+            .collect();
+        assert!(allocator.malloc(16).is_none());
+        allocator.free(handles[0]);
+        assert!(allocator.malloc(16).is_some());
+    });
+}
+
+#[test]
+fn test_compact() {
+    with_allocator(|mut allocator| {
+        let mut handles = Vec::new();
+        while let Some(h) = allocator.malloc(1024) {
+            handles.push(h);
+        }
+        // free every other block, none of the free blocks can merge
+        let mut kept = Vec::new();
+        for (i, h) in handles.into_iter().enumerate() {
+            if i % 2 == 0 {
+                allocator.free(h);
+            } else {
+                fill(&allocator, h, i as u8);
+                kept.push((h, i as u8));
+            }
+        }
+        let before = allocator.stats();
+        assert!(allocator.compact() > 0);
+        let after = allocator.stats();
+        assert_eq!(after.free_bytes, before.free_bytes);
+        assert!(after.largest_free_block > before.largest_free_block);
+        assert!(after.free_blocks < before.free_blocks);
+        // contents are moved with the blocks
+        for (h, byte) in kept {
+            assert!(is_filled(&allocator, h, byte));
+        }
+    });
+}
+
+#[test]
+fn test_compact_skips_pinned() {
+    with_allocator(|mut allocator| {
+        let a = allocator.malloc(256).unwrap();
+        let b = allocator.malloc(256).unwrap();
+        allocator.free(a);
+        let p = allocator.pin(b);
+        assert_eq!(allocator.compact(), 0);
+        assert_eq!(allocator.pin(b), p);
+        allocator.unpin(b);
+        allocator.unpin(b);
+        assert_eq!(allocator.compact(), 1);
+        assert!((allocator.pin(b) as usize) < p as usize);
+    });
+}
+
+#[test]
+#[should_panic(expected = "free a pinned block")]
+fn test_free_pinned() {
+    with_allocator(|mut allocator| {
+        let h = allocator.malloc(16).unwrap();
+        allocator.pin(h);
+        allocator.free(h);
+    });
+}
@@ -1,2 +1,8 @@
 mod buddy_alloc;
+mod chunked_fast_alloc;
 mod fast_alloc;
+mod handle_alloc;
+mod non_threadsafe_alloc;
+mod raw_alloc;
+mod slab_alloc;
+mod zone_alloc;
@@ -0,0 +1,601 @@
+use crate::buddy_alloc::BuddyAllocParam;
+use crate::chunked_fast_alloc::ChunkedFastAllocParam;
+use crate::fast_alloc::FastAllocParam;
+use crate::non_threadsafe_alloc::NonThreadsafeAlloc;
+use crate::oom::{OomAction, OomPolicy};
+use crate::poison::{Poison, ALLOC_PATTERN};
+use crate::redzone::RedzoneSide;
+use crate::reentrancy::ReentrancyPolicy;
+use crate::slab_alloc::{SizeClassParam, SlabAllocParam, SlabFallback};
+use core::alloc::{GlobalAlloc, Layout};
+
+const FAST_HEAP_SIZE: usize = 4096;
+const HEAP_SIZE: usize = 64 * 1024;
+const LEAF_SIZE: usize = 16;
+
+#[repr(align(64))]
+struct AlignedBuf<const S: usize>([u8; S]);
+
+fn with_allocator<
+    F: FnOnce(NonThreadsafeAlloc) -> NonThreadsafeAlloc,
+    T: FnOnce(&NonThreadsafeAlloc),
+>(
+    config: F,
+    f: T,
+) {
+    let fast_heap = Box::new(AlignedBuf([0u8; FAST_HEAP_SIZE]));
+    let heap = Box::new(AlignedBuf([0u8; HEAP_SIZE]));
+    let allocator = config(NonThreadsafeAlloc::new(
+        FastAllocParam::new(fast_heap.0.as_ptr(), FAST_HEAP_SIZE),
+        BuddyAllocParam::new(heap.0.as_ptr(), HEAP_SIZE, LEAF_SIZE),
+    ));
+    f(&allocator);
+}
+
+#[test]
+fn test_redzone_alloc_and_dealloc() {
+    with_allocator(
+        |alloc| alloc.with_redzone(16),
+        |allocator| unsafe {
+            let mut ptrs = Vec::new();
+            for &(size, align) in &[(1, 1), (24, 8), (64, 16), (100, 32), (4096, 64)] {
+                let layout = Layout::from_size_align(size, align).unwrap();
+                let p = allocator.alloc(layout);
+                assert!(!p.is_null());
+                assert_eq!(p as usize % align, 0);
+                core::ptr::write_bytes(p, 0x42, size);
+                ptrs.push((p, layout));
+            }
+            assert!(allocator.check_all_redzones().is_ok());
+            for (p, layout) in ptrs {
+                allocator.dealloc(p, layout);
+            }
+            assert!(allocator.check_all_redzones().is_ok());
+        },
+    );
+}
+
+#[test]
+fn test_redzone_detect_overflow() {
+    with_allocator(
+        |alloc| alloc.with_redzone(16),
+        |allocator| unsafe {
+            let layout = Layout::from_size_align(40, 8).unwrap();
+            let p1 = allocator.alloc(layout);
+            let p2 = allocator.alloc(layout);
+            p2.add(40).write(0);
+            let err = allocator.check_all_redzones().unwrap_err();
+            assert_eq!(err.ptr, p2);
+            assert_eq!(err.side, RedzoneSide::Back);
+            assert_eq!(err.addr, p2.add(40));
+            p2.add(40).write(crate::redzone::REDZONE_CANARY);
+            p1.sub(1).write(0);
+            let err = allocator.check_all_redzones().unwrap_err();
+            assert_eq!(err.ptr, p1);
+            assert_eq!(err.side, RedzoneSide::Front);
+        },
+    );
+}
+
+#[test]
+#[should_panic(expected = "back redzone")]
+fn test_redzone_dealloc_panics_on_corruption() {
+    with_allocator(
+        |alloc| alloc.with_redzone(8),
+        |allocator| unsafe {
+            let layout = Layout::from_size_align(200, 8).unwrap();
+            let p = allocator.alloc(layout);
+            p.add(203).write(0);
+            allocator.dealloc(p, layout);
+        },
+    );
+}
+
+#[test]
+fn test_poison() {
+    with_allocator(
+        |alloc| alloc.with_poison(Poison::new(true)),
+        |allocator| unsafe {
+            for &size in &[8, 64, 65, 1000] {
+                let layout = Layout::from_size_align(size, 8).unwrap();
+                let p = allocator.alloc(layout);
+                let block = core::slice::from_raw_parts(p, size);
+                assert!(block.iter().all(|&b| b == ALLOC_PATTERN));
+                allocator.dealloc(p, layout);
+            }
+        },
+    );
+}
+
+#[test]
+fn test_alloc_zeroed() {
+    with_allocator(
+        |alloc| alloc,
+        |allocator| unsafe {
+            for _ in 0..3 {
+                let mut ptrs = Vec::new();
+                for &size in &[8, 64, 65, 1000, 16 * 1024] {
+                    let layout = Layout::from_size_align(size, 8).unwrap();
+                    let p = allocator.alloc_zeroed(layout);
+                    let block = core::slice::from_raw_parts_mut(p, size);
+                    assert!(block.iter().all(|&b| b == 0));
+                    block.fill(0xFF);
+                    ptrs.push((p, layout));
+                }
+                for (p, layout) in ptrs {
+                    allocator.dealloc(p, layout);
+                }
+            }
+        },
+    );
+}
+
+#[test]
+fn test_size_classes() {
+    const POOL_SIZE: usize = 1024;
+    let classes: Vec<SizeClassParam> = [16, 48, 96]
+        .iter()
+        .map(|&size| {
+            let buf = Box::leak(Box::new(AlignedBuf([0u8; POOL_SIZE])));
+            SizeClassParam::new(size, buf.0.as_ptr(), POOL_SIZE)
+        })
+        .collect();
+    let classes = Box::leak(classes.into_boxed_slice());
+    let heap = Box::new(AlignedBuf([0u8; HEAP_SIZE]));
+    for &fallback in &[SlabFallback::Buddy, SlabFallback::Fail] {
+        let allocator = NonThreadsafeAlloc::new_with_size_classes(
+            SlabAllocParam::new_with_fallback(classes, fallback),
+            BuddyAllocParam::new(heap.0.as_ptr(), HEAP_SIZE, LEAF_SIZE),
+        );
+        let in_class = |p: *mut u8, i: usize| {
+            let base = classes[i].base_addr as usize;
+            p as usize >= base && (p as usize) < base + POOL_SIZE
+        };
+        unsafe {
+            let mut ptrs = Vec::new();
+            for _ in 0..POOL_SIZE / 96 {
+                let p = allocator.alloc(layout(80));
+                assert!(in_class(p, 2));
+                ptrs.push((p, layout(80)));
+            }
+            // the class is exhausted
+            let p = allocator.alloc(layout(80));
+            match fallback {
+                SlabFallback::Fail => assert!(p.is_null()),
+                _ => {
+                    assert!(!p.is_null() && !in_class(p, 2));
+                    ptrs.push((p, layout(80)));
+                }
+            }
+            // no class fits
+            let p = allocator.alloc(layout(200));
+            assert!(!p.is_null());
+            ptrs.push((p, layout(200)));
+            let p = allocator.alloc(layout(30));
+            assert!(in_class(p, 1));
+            ptrs.push((p, layout(30)));
+            for (p, layout) in ptrs {
+                allocator.dealloc(p, layout);
+            }
+        }
+    }
+}
+
+fn layout(size: usize) -> Layout {
+    Layout::from_size_align(size, 8).unwrap()
+}
+
+#[test]
+fn test_fast_refill() {
+    let heap = Box::new(AlignedBuf([0u8; HEAP_SIZE]));
+    let allocator = NonThreadsafeAlloc::new_with_fast_refill(
+        ChunkedFastAllocParam::new(1024),
+        BuddyAllocParam::new(heap.0.as_ptr(), HEAP_SIZE, LEAF_SIZE),
+    );
+    unsafe {
+        for _ in 0..3 {
+            let mut ptrs = Vec::new();
+            for i in 0..200 {
+                let size = [8, 64, 65, 300][i % 4];
+                let p = allocator.alloc(layout(size));
+                assert!(!p.is_null());
+                core::ptr::write_bytes(p, 0xFF, size);
+                ptrs.push((p, layout(size)));
+            }
+            for (p, layout) in ptrs.into_iter().rev() {
+                allocator.dealloc(p, layout);
+            }
+            // chunks are given back
+            let p = allocator.alloc(layout(HEAP_SIZE / 2));
+            assert!(!p.is_null());
+            allocator.dealloc(p, layout(HEAP_SIZE / 2));
+        }
+    }
+}
+
+#[test]
+fn test_realloc() {
+    with_allocator(
+        |alloc| alloc,
+        |allocator| unsafe {
+            // the fast block fits
+            let p = allocator.alloc(layout(40));
+            core::ptr::write_bytes(p, 0x42, 40);
+            assert_eq!(allocator.realloc(p, layout(40), 60), p);
+            // migrate to buddy
+            let q = allocator.realloc(p, layout(60), 200);
+            assert_ne!(q, p);
+            let block = core::slice::from_raw_parts(q, 200);
+            assert!(block[..40].iter().all(|&b| b == 0x42));
+            // the buddy block fits
+            let r = allocator.realloc(q, layout(200), 256);
+            assert_eq!(r, q);
+            assert!(core::slice::from_raw_parts(r, 40)
+                .iter()
+                .all(|&b| b == 0x42));
+            // migrate back to fast
+            let s = allocator.realloc(r, layout(256), 16);
+            assert!(core::slice::from_raw_parts(s, 16)
+                .iter()
+                .all(|&b| b == 0x42));
+            allocator.dealloc(s, layout(16));
+        },
+    );
+}
+
+#[test]
+fn test_oom_return_null() {
+    with_allocator(
+        |alloc| alloc.with_oom_policy(OomPolicy::ReturnNull),
+        |allocator| unsafe {
+            assert!(allocator.alloc(layout(HEAP_SIZE)).is_null());
+        },
+    );
+}
+
+#[test]
+#[should_panic(expected = "out of memory: failed to allocate 65536 bytes (align 8)")]
+fn test_oom_panic() {
+    with_allocator(
+        |alloc| alloc.with_oom_policy(OomPolicy::Panic),
+        |allocator| unsafe {
+            allocator.alloc(layout(HEAP_SIZE));
+        },
+    );
+}
+
+#[test]
+fn test_oom_emergency_reserve() {
+    let reserve = Box::new(AlignedBuf([0u8; HEAP_SIZE]));
+    with_allocator(
+        |alloc| {
+            alloc.with_oom_policy(OomPolicy::EmergencyReserve(BuddyAllocParam::new(
+                reserve.0.as_ptr(),
+                HEAP_SIZE,
+                LEAF_SIZE,
+            )))
+        },
+        |allocator| unsafe {
+            let reserve_range = reserve.0.as_ptr_range();
+            let largest = allocator.stats().largest_free_block;
+            let p = allocator.alloc(layout(largest));
+            assert!(!p.is_null() && !reserve_range.contains(&(p as *const u8)));
+            // the main heap is exhausted
+            let q = allocator.alloc(layout(largest));
+            assert!(reserve_range.contains(&(q as *const u8)));
+            core::ptr::write_bytes(q, 0x42, largest);
+            allocator.dealloc(p, layout(largest));
+            // migrate back to the main heap
+            let r = allocator.realloc(q, layout(largest), 1024);
+            assert!(!r.is_null() && !reserve_range.contains(&(r as *const u8)));
+            assert!(core::slice::from_raw_parts(r, 1024)
+                .iter()
+                .all(|&b| b == 0x42));
+            allocator.dealloc(r, layout(1024));
+        },
+    );
+}
+
+#[test]
+fn test_oom_emergency_reserve_zeroize() {
+    let reserve = Box::new(AlignedBuf([0u8; HEAP_SIZE]));
+    with_allocator(
+        |alloc| {
+            alloc
+                .with_zeroize()
+                .with_oom_policy(OomPolicy::EmergencyReserve(BuddyAllocParam::new(
+                    reserve.0.as_ptr(),
+                    HEAP_SIZE,
+                    LEAF_SIZE,
+                )))
+        },
+        |allocator| unsafe {
+            let reserve_range = reserve.0.as_ptr_range();
+            let largest = allocator.stats().largest_free_block;
+            let p = allocator.alloc(layout(largest));
+            // the main heap is exhausted
+            let q = allocator.alloc(layout(largest));
+            assert!(reserve_range.contains(&(q as *const u8)));
+            core::ptr::write_bytes(q, 0x42, 1024);
+            allocator.dealloc(q, layout(largest));
+            // the free list node is written after wiping
+            assert!(core::slice::from_raw_parts(q, 1024)
+                .iter()
+                .skip(32)
+                .all(|&b| b == 0));
+            allocator.dealloc(p, layout(largest));
+        },
+    );
+}
+
+#[test]
+fn test_oom_emergency_reserve_untouched() {
+    let reserve = Box::new(AlignedBuf([0xcc; HEAP_SIZE]));
+    with_allocator(
+        |alloc| {
+            alloc.with_oom_policy(OomPolicy::EmergencyReserve(BuddyAllocParam::new(
+                reserve.0.as_ptr(),
+                HEAP_SIZE,
+                LEAF_SIZE,
+            )))
+        },
+        |allocator| unsafe {
+            let p = allocator.alloc(layout(1024));
+            let p = allocator.realloc(p, layout(1024), 2048);
+            assert!(!p.is_null());
+            allocator.dealloc(p, layout(2048));
+            // the reserve is not built before the main heap fails
+            assert!(reserve.0.iter().all(|&b| b == 0xcc));
+            let largest = allocator.stats().largest_free_block;
+            let p = allocator.alloc(layout(largest));
+            let q = allocator.alloc(layout(largest));
+            assert!(reserve.0.as_ptr_range().contains(&(q as *const u8)));
+            allocator.dealloc(q, layout(largest));
+            allocator.dealloc(p, layout(largest));
+        },
+    );
+}
+
+std::thread_local! {
+    static OOM_CALLS: core::cell::Cell<usize> = const { core::cell::Cell::new(0) };
+}
+
+#[test]
+fn test_oom_callback() {
+    fn handler(layout: Layout, stats: crate::buddy_alloc::Stats) -> OomAction {
+        assert_eq!(layout.size(), HEAP_SIZE);
+        assert!(stats.largest_free_block < HEAP_SIZE);
+        let calls = OOM_CALLS.with(|calls| {
+            calls.set(calls.get() + 1);
+            calls.get()
+        });
+        if calls < 3 {
+            OomAction::Retry
+        } else {
+            OomAction::Fail
+        }
+    }
+    with_allocator(
+        |alloc| alloc.with_oom_policy(OomPolicy::Callback(handler)),
+        |allocator| unsafe {
+            assert!(allocator.alloc(layout(HEAP_SIZE)).is_null());
+            assert_eq!(OOM_CALLS.with(|calls| calls.get()), 3);
+        },
+    );
+}
+
+std::thread_local! {
+    static UNINIT_CALLS: core::cell::Cell<usize> = const { core::cell::Cell::new(0) };
+}
+
+#[test]
+fn test_init() {
+    fn hook(layout: Layout) {
+        assert_eq!(layout.size(), 40);
+        UNINIT_CALLS.with(|calls| calls.set(calls.get() + 1));
+    }
+    let fast_heap = Box::new(AlignedBuf([0u8; FAST_HEAP_SIZE]));
+    let heap = Box::new(AlignedBuf([0u8; HEAP_SIZE]));
+    let allocator = NonThreadsafeAlloc::empty()
+        .with_poison(Poison::new(true))
+        .with_uninit_hook(hook);
+    assert!(!allocator.is_initialized());
+    unsafe {
+        assert!(allocator.alloc(layout(40)).is_null());
+        assert_eq!(UNINIT_CALLS.with(|calls| calls.get()), 1);
+        allocator.init(
+            FastAllocParam::new(fast_heap.0.as_ptr(), FAST_HEAP_SIZE),
+            BuddyAllocParam::new(heap.0.as_ptr(), HEAP_SIZE, LEAF_SIZE),
+        );
+        assert!(allocator.is_initialized());
+        for &size in &[40, 1000] {
+            let p = allocator.alloc(layout(size));
+            assert!(!p.is_null());
+            // the poison mode is applied
+            let block = core::slice::from_raw_parts(p, size);
+            assert!(block.iter().all(|&b| b == ALLOC_PATTERN));
+            allocator.dealloc(p, layout(size));
+        }
+    }
+    assert_eq!(UNINIT_CALLS.with(|calls| calls.get()), 1);
+}
+
+#[test]
+#[should_panic(expected = "NonThreadsafeAlloc is already initialized")]
+fn test_init_twice() {
+    with_allocator(
+        |alloc| alloc,
+        |allocator| unsafe {
+            let heap = Box::new(AlignedBuf([0u8; HEAP_SIZE]));
+            allocator.init(
+                FastAllocParam::new(heap.0.as_ptr(), FAST_HEAP_SIZE),
+                BuddyAllocParam::new(heap.0.as_ptr(), HEAP_SIZE, LEAF_SIZE),
+            );
+        },
+    );
+}
+
+#[test]
+fn test_reentrancy_return_null() {
+    with_allocator(
+        |alloc| alloc,
+        |allocator| unsafe {
+            let stats = allocator.stats();
+            let p = allocator.alloc(layout(1000));
+            let in_use = allocator.stats();
+            allocator
+                .exclusive(|| {
+                    // nested requests
+                    assert!(allocator.alloc(layout(1000)).is_null());
+                    assert!(allocator.realloc(p, layout(1000), 2000).is_null());
+                    assert_eq!(allocator.stats(), Default::default());
+                    allocator.dealloc(p, layout(1000));
+                    // the free is deferred
+                    assert!(allocator.exclusive(|| ()).is_none());
+                })
+                .unwrap();
+            assert_ne!(in_use, stats);
+            assert_eq!(allocator.stats(), stats);
+        },
+    );
+}
+
+std::thread_local! {
+    static NESTED_CALLS: core::cell::Cell<usize> = const { core::cell::Cell::new(0) };
+}
+
+#[test]
+fn test_reentrancy_hook() {
+    fn hook(layout: Layout) {
+        assert_eq!(layout.size(), 40);
+        NESTED_CALLS.with(|calls| calls.set(calls.get() + 1));
+    }
+    with_allocator(
+        |alloc| alloc.with_reentrancy_policy(ReentrancyPolicy::Hook(hook)),
+        |allocator| unsafe {
+            let p = allocator.exclusive(|| allocator.alloc(layout(40))).unwrap();
+            assert!(p.is_null());
+            assert_eq!(NESTED_CALLS.with(|calls| calls.get()), 1);
+            assert!(!allocator.alloc(layout(40)).is_null());
+            assert_eq!(NESTED_CALLS.with(|calls| calls.get()), 1);
+        },
+    );
+}
+
+#[test]
+fn test_reentrancy_reserve() {
+    const RESERVE_SIZE: usize = 4096;
+    let reserve = Box::new(AlignedBuf([0u8; RESERVE_SIZE]));
+    with_allocator(
+        |alloc| {
+            alloc
+                .with_redzone(8)
+                .with_reentrancy_policy(ReentrancyPolicy::Reserve(BuddyAllocParam::new(
+                    reserve.0.as_ptr(),
+                    RESERVE_SIZE,
+                    LEAF_SIZE,
+                )))
+        },
+        |allocator| unsafe {
+            let reserve_range = reserve.0.as_ptr_range();
+            let (p, q) = allocator
+                .exclusive(|| {
+                    let p = allocator.alloc_zeroed(layout(100));
+                    assert!(reserve_range.contains(&(p as *const u8)));
+                    assert!(core::slice::from_raw_parts(p, 100).iter().all(|&b| b == 0));
+                    core::ptr::write_bytes(p, 0x42, 100);
+                    // the reserve is freed right away
+                    let q = allocator.realloc(p, layout(100), 200);
+                    assert!(reserve_range.contains(&(q as *const u8)));
+                    let p = allocator.alloc(layout(100));
+                    assert!(reserve_range.contains(&(p as *const u8)));
+                    (p, q)
+                })
+                .unwrap();
+            assert!(core::slice::from_raw_parts(q, 100)
+                .iter()
+                .all(|&b| b == 0x42));
+            // blocks of the reserve are migrated back to the main heap
+            let r = allocator.realloc(q, layout(200), 300);
+            assert!(!reserve_range.contains(&(r as *const u8)));
+            assert!(core::slice::from_raw_parts(r, 100)
+                .iter()
+                .all(|&b| b == 0x42));
+            allocator.dealloc(p, layout(100));
+            allocator.dealloc(r, layout(300));
+            assert!(allocator.check_all_redzones().is_ok());
+        },
+    );
+}
+
+#[test]
+fn test_reentrancy_reserve_modes() {
+    const RESERVE_SIZE: usize = 4096;
+    let reserve = Box::new(AlignedBuf([0u8; RESERVE_SIZE]));
+    with_allocator(
+        |alloc| {
+            alloc
+                .with_poison(Poison::new(true))
+                .with_reentrancy_policy(ReentrancyPolicy::Reserve(BuddyAllocParam::new(
+                    reserve.0.as_ptr(),
+                    RESERVE_SIZE,
+                    LEAF_SIZE,
+                )))
+        },
+        |allocator| unsafe {
+            let reserve_range = reserve.0.as_ptr_range();
+            allocator
+                .exclusive(|| {
+                    let p = allocator.alloc(layout(100));
+                    assert!(reserve_range.contains(&(p as *const u8)));
+                    let block = core::slice::from_raw_parts(p, 100);
+                    assert!(block.iter().all(|&b| b == ALLOC_PATTERN));
+                    allocator.dealloc(p, layout(100));
+                })
+                .unwrap();
+        },
+    );
+}
+
+#[test]
+fn test_reentrancy_reserve_untouched() {
+    const RESERVE_SIZE: usize = 4096;
+    let reserve = Box::new(AlignedBuf([0xcc; RESERVE_SIZE]));
+    with_allocator(
+        |alloc| {
+            alloc.with_reentrancy_policy(ReentrancyPolicy::Reserve(BuddyAllocParam::new(
+                reserve.0.as_ptr(),
+                RESERVE_SIZE,
+                LEAF_SIZE,
+            )))
+        },
+        |allocator| unsafe {
+            let p = allocator.alloc(layout(100));
+            let p = allocator.realloc(p, layout(100), 2048);
+            assert!(!p.is_null());
+            allocator.dealloc(p, layout(2048));
+            // the reserve is not built before a nested request
+            assert!(reserve.0.iter().all(|&b| b == 0xcc));
+        },
+    );
+}
+
+#[test]
+fn test_reentrancy_after_panic() {
+    with_allocator(
+        |alloc| alloc.with_poison(Poison::new(true)),
+        |allocator| unsafe {
+            let p = allocator.alloc(layout(1000));
+            allocator.dealloc(p, layout(1000));
+            p.add(100).write(0);
+            let result = std::panic::catch_unwind(core::panic::AssertUnwindSafe(|| {
+                allocator.alloc(layout(1000));
+            }));
+            assert!(result.is_err());
+            // the allocator is released on unwinding
+            let p = allocator.alloc(layout(40));
+            assert!(!p.is_null());
+            allocator.dealloc(p, layout(40));
+        },
+    );
+}
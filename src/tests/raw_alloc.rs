@@ -0,0 +1,131 @@
+use crate::buddy_alloc::{BuddyAlloc, BuddyAllocParam};
+use crate::fast_alloc::{FastAlloc, FastAllocParam, BLOCK_SIZE};
+use crate::raw_alloc::{Bucketizer, Fallback, RawAlloc, RawGlobalAlloc, Segregator};
+use core::alloc::{GlobalAlloc, Layout};
+
+const HEAP_SIZE: usize = 64 * 1024;
+const LEAF_SIZE: usize = 16;
+
+fn layout(size: usize) -> Layout {
+    Layout::from_size_align(size, 8).unwrap()
+}
+
+/// a leaked heap aligned to the block size of FastAlloc
+fn heap(size: usize) -> *const u8 {
+    unsafe { std::alloc::alloc(Layout::from_size_align(size, BLOCK_SIZE).unwrap()) }
+}
+
+unsafe fn buddy(size: usize) -> BuddyAlloc {
+    BuddyAlloc::new(BuddyAllocParam::new(heap(size), size, LEAF_SIZE))
+}
+
+unsafe fn fast(size: usize) -> FastAlloc {
+    FastAlloc::new(FastAllocParam::new(heap(size), size))
+}
+
+#[test]
+fn test_segregator() {
+    let mut alloc = unsafe { Segregator::<BLOCK_SIZE, _, _>::new(fast(4096), buddy(HEAP_SIZE)) };
+    let p = alloc.malloc(layout(40));
+    assert!(alloc.small.contains(p));
+    assert_eq!(alloc.usable_size(p), BLOCK_SIZE);
+    let q = alloc.malloc(layout(100));
+    assert!(alloc.large.contains(q));
+    assert_eq!(alloc.usable_size(q), 128);
+    alloc.free(p, layout(40));
+    alloc.free(q, layout(100));
+    assert_eq!(alloc.malloc(layout(40)), p);
+}
+
+#[test]
+fn test_fallback() {
+    let mut alloc = unsafe { Fallback::new(fast(BLOCK_SIZE * 4), buddy(HEAP_SIZE)) };
+    let ptrs: Vec<_> = (0..6).map(|_| alloc.malloc(layout(40))).collect();
+    assert!(ptrs[..4].iter().all(|&p| alloc.primary.contains(p)));
+    assert!(ptrs[4..].iter().all(|&p| alloc.fallback.contains(p)));
+    assert!(ptrs.iter().all(|&p| alloc.contains(p)));
+    for p in ptrs {
+        alloc.free(p, layout(40));
+    }
+    let p = alloc.malloc(layout(40));
+    assert!(alloc.primary.contains(p));
+}
+
+#[test]
+fn test_bucketizer() {
+    let mut alloc = unsafe { Bucketizer::new(64, 64, [buddy(HEAP_SIZE), buddy(HEAP_SIZE)]) };
+    assert!(alloc.malloc(layout(64)).is_null());
+    assert!(alloc.malloc(layout(193)).is_null());
+    let p = alloc.malloc(layout(65));
+    assert!(alloc.buckets[0].contains(p));
+    let q = alloc.malloc(layout(192));
+    assert!(alloc.buckets[1].contains(q));
+    assert_eq!(alloc.usable_size(q), 256);
+    alloc.free(p, layout(65));
+    alloc.free(q, layout(192));
+}
+
+#[test]
+fn test_alignment() {
+    let mut alloc = unsafe { buddy(HEAP_SIZE) };
+    let initial = alloc.stats();
+    // alignments larger than the leaf size
+    for &align in &[16, 64, 256, 1024, 4096] {
+        let layout = Layout::from_size_align(24, align).unwrap();
+        let p = RawAlloc::malloc(&mut alloc, layout);
+        assert!(!p.is_null());
+        assert_eq!(p as usize % align, 0);
+        assert!(alloc.usable_size(p) >= 24);
+        unsafe { core::ptr::write_bytes(p, 0x42, 24) };
+        RawAlloc::free(&mut alloc, p, layout);
+    }
+    assert_eq!(alloc.stats(), initial);
+}
+
+#[test]
+fn test_raw_global_alloc() {
+    let allocator = RawGlobalAlloc::empty();
+    unsafe {
+        assert!(allocator.alloc(layout(40)).is_null());
+        allocator.init(Segregator::<BLOCK_SIZE, _, _>::new(
+            fast(4096),
+            buddy(HEAP_SIZE),
+        ));
+        let p = allocator.alloc(layout(40));
+        core::ptr::write_bytes(p, 0x42, 40);
+        // the fast block fits
+        assert_eq!(allocator.realloc(p, layout(40), 60), p);
+        let q = allocator.realloc(p, layout(60), 200);
+        assert!(core::slice::from_raw_parts(q, 40)
+            .iter()
+            .all(|&b| b == 0x42));
+        // nested requests fail
+        let nested = allocator.with_alloc(|_| allocator.alloc(layout(40)));
+        assert_eq!(nested, Some(core::ptr::null_mut()));
+        allocator.dealloc(q, layout(200));
+    }
+}
+
+#[test]
+fn test_realloc_across_threshold() {
+    unsafe {
+        let allocator =
+            RawGlobalAlloc::new(Segregator::<32, _, _>::new(fast(4096), buddy(HEAP_SIZE)));
+        let p = allocator.alloc(layout(20));
+        // kept in the small allocator, but freed with a large layout
+        assert_eq!(allocator.realloc(p, layout(20), 50), p);
+        allocator.dealloc(p, layout(50));
+        assert_eq!(allocator.alloc(layout(20)), p);
+
+        let allocator = RawGlobalAlloc::new(Bucketizer::new(
+            64,
+            32,
+            [buddy(HEAP_SIZE), buddy(HEAP_SIZE)],
+        ));
+        let p = allocator.alloc(layout(65));
+        // the 128 bytes block of the first bucket fits the size of the second bucket
+        assert_eq!(allocator.realloc(p, layout(65), 120), p);
+        allocator.dealloc(p, layout(120));
+        assert_eq!(allocator.alloc(layout(65)), p);
+    }
+}
@@ -0,0 +1,114 @@
+use crate::slab_alloc::{SizeClassParam, SlabAlloc, SlabAllocParam, SlabFallback};
+use core::alloc::Layout;
+
+const CLASS_SIZES: [usize; 7] = [16, 32, 48, 64, 96, 128, 256];
+const POOL_SIZE: usize = 4096;
+
+#[repr(align(256))]
+struct AlignedBuf([u8; POOL_SIZE]);
+
+/// leak pools, since size classes must be static
+fn size_classes() -> &'static [SizeClassParam] {
+    let classes: Vec<SizeClassParam> = CLASS_SIZES
+        .iter()
+        .map(|&size| {
+            let buf = Box::leak(Box::new(AlignedBuf([0u8; POOL_SIZE])));
+            SizeClassParam::new(size, buf.0.as_ptr(), POOL_SIZE)
+        })
+        .collect();
+    Box::leak(classes.into_boxed_slice())
+}
+
+fn layout(size: usize) -> Layout {
+    Layout::from_size_align(size, 8).unwrap()
+}
+
+#[test]
+fn test_malloc_smallest_class() {
+    let classes = size_classes();
+    let mut allocator = unsafe { SlabAlloc::new(SlabAllocParam::new(classes)) };
+    for (i, &size) in CLASS_SIZES.iter().enumerate() {
+        let p = allocator.malloc(layout(size - 8));
+        let base = classes[i].base_addr as usize;
+        assert!(p as usize >= base && (p as usize) < base + POOL_SIZE);
+        let p = allocator.malloc(layout(size));
+        assert!(p as usize >= base && (p as usize) < base + POOL_SIZE);
+    }
+    // no class fits
+    assert!(!allocator.serves(layout(257)));
+    assert!(allocator.malloc(layout(257)).is_null());
+    assert!(!allocator.serves(Layout::from_size_align(16, 512).unwrap()));
+}
+
+#[test]
+fn test_fallback_larger_class() {
+    let classes = size_classes();
+    let mut allocator = unsafe { SlabAlloc::new(SlabAllocParam::new(classes)) };
+    let mut ptrs = Vec::new();
+    for _ in 0..POOL_SIZE / 16 {
+        let p = allocator.malloc(layout(16));
+        assert!(allocator.contains_ptr(p));
+        ptrs.push(p);
+    }
+    // class 16 is exhausted, served by class 32
+    let p = allocator.malloc(layout(16));
+    let base = classes[1].base_addr as usize;
+    assert!(p as usize >= base && (p as usize) < base + POOL_SIZE);
+    allocator.free(p, layout(16));
+    assert_eq!(allocator.malloc(layout(32)), p);
+    for p in ptrs {
+        allocator.free(p, layout(16));
+    }
+}
+
+#[test]
+fn test_fallback_fail() {
+    let classes = size_classes();
+    for &fallback in &[SlabFallback::Buddy, SlabFallback::Fail] {
+        let param = SlabAllocParam::new_with_fallback(classes, fallback);
+        let mut allocator = unsafe { SlabAlloc::new(param) };
+        for _ in 0..POOL_SIZE / 256 {
+            assert!(!allocator.malloc(layout(200)).is_null());
+        }
+        assert!(allocator.malloc(layout(200)).is_null());
+    }
+}
+
+#[test]
+fn test_malloc_and_free() {
+    let classes = size_classes();
+    let mut allocator = unsafe { SlabAlloc::new(SlabAllocParam::new(classes)) };
+    for _ in 0..3 {
+        let mut ptrs = Vec::new();
+        for &size in CLASS_SIZES.iter().cycle().take(100) {
+            let p = allocator.malloc(layout(size));
+            assert!(!p.is_null());
+            unsafe { core::ptr::write_bytes(p, 0xFF, size) };
+            ptrs.push((p, size));
+        }
+        for (p, size) in ptrs {
+            allocator.free(p, layout(size));
+        }
+    }
+}
+
+#[test]
+fn test_pool_at_top_of_address_space() {
+    // blocks are never touched without poison and free
+    let base = 0usize.wrapping_sub(POOL_SIZE);
+    let classes = Box::leak(Box::new([SizeClassParam::new(
+        256,
+        base as *const u8,
+        POOL_SIZE,
+    )]));
+    let mut allocator = unsafe { SlabAlloc::new(SlabAllocParam::new(classes)) };
+    let ptrs: Vec<_> = (0..POOL_SIZE / 256)
+        .map(|_| allocator.malloc(layout(200)))
+        .collect();
+    assert_eq!(ptrs[0] as usize, base);
+    assert_eq!(*ptrs.last().unwrap() as usize, usize::MAX - 255);
+    assert!(ptrs.iter().all(|&p| allocator.contains_ptr(p)));
+    assert!(!allocator.contains_ptr((base - 1) as *mut u8));
+    // exhausted without overflowing
+    assert!(allocator.malloc(layout(200)).is_null());
+}
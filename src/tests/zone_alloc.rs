@@ -0,0 +1,89 @@
+use crate::buddy_alloc::{BuddyAllocParam, MIN_LEAF_SIZE_ALIGN};
+use crate::zone_alloc::{ZoneAlloc, ZoneAllocParam, ZoneFlags, ZoneParam};
+use core::alloc::Layout;
+
+const ZONE_SIZE: usize = 64 * 1024;
+const LEAF_SIZE: usize = MIN_LEAF_SIZE_ALIGN;
+
+/// leak heaps, since zones must be static
+fn zone(flags: ZoneFlags) -> ZoneParam {
+    let buf: &'static mut Vec<u8> = Box::leak(Box::new(Vec::with_capacity(ZONE_SIZE)));
+    ZoneParam::new(
+        BuddyAllocParam::new(buf.as_ptr(), ZONE_SIZE, LEAF_SIZE),
+        flags,
+    )
+}
+
+/// normal memory, then fast memory, then the DMA zone
+fn zone_alloc() -> ZoneAlloc {
+    let zones = Box::leak(Box::new([
+        zone(ZoneFlags::NONE),
+        zone(ZoneFlags::FAST),
+        zone(ZoneFlags::DMA),
+    ]));
+    unsafe { ZoneAlloc::new(ZoneAllocParam::new(zones)) }
+}
+
+fn layout(size: usize) -> Layout {
+    Layout::from_size_align(size, 8).unwrap()
+}
+
+#[test]
+fn test_alloc_in() {
+    let mut allocator = zone_alloc();
+    let p = allocator.alloc_in(layout(100), ZoneFlags::DMA);
+    assert_eq!(allocator.zone_flags(p), Some(ZoneFlags::DMA));
+    let p = allocator.alloc_in(layout(100), ZoneFlags::FAST);
+    assert_eq!(allocator.zone_flags(p), Some(ZoneFlags::FAST));
+    let p = allocator.alloc_in(layout(100), ZoneFlags::NONE);
+    assert_eq!(allocator.zone_flags(p), Some(ZoneFlags::NONE));
+    // no zone has both flags
+    assert!(allocator
+        .alloc_in(layout(100), ZoneFlags::DMA | ZoneFlags::FAST)
+        .is_null());
+    assert_eq!(allocator.zone_flags(core::ptr::null()), None);
+}
+
+#[test]
+fn test_alloc_in_aligned() {
+    let mut allocator = zone_alloc();
+    // e.g. DMA descriptors aligned to a page
+    let layout = Layout::from_size_align(100, 4096).unwrap();
+    let p = allocator.alloc_in(layout, ZoneFlags::DMA);
+    assert!(!p.is_null());
+    assert_eq!(p as usize % 4096, 0);
+    assert_eq!(allocator.zone_flags(p), Some(ZoneFlags::DMA));
+    allocator.dealloc(p, layout);
+}
+
+#[test]
+fn test_fallback_order() {
+    let mut allocator = zone_alloc();
+    let mut zones = Vec::new();
+    loop {
+        let p = allocator.alloc_in(layout(1024), ZoneFlags::NONE);
+        if p.is_null() {
+            break;
+        }
+        let flags = allocator.zone_flags(p).unwrap();
+        if zones.last() != Some(&flags) {
+            zones.push(flags);
+        }
+    }
+    // zones are exhausted in order
+    assert_eq!(zones, [ZoneFlags::NONE, ZoneFlags::FAST, ZoneFlags::DMA]);
+}
+
+#[test]
+fn test_zone_stats() {
+    let mut allocator = zone_alloc();
+    let initial: Vec<_> = allocator.stats().collect();
+    let p = allocator.alloc_in(layout(1024), ZoneFlags::DMA);
+    let stats: Vec<_> = allocator.stats().collect();
+    assert_eq!(stats[0], initial[0]);
+    assert_eq!(stats[1], initial[1]);
+    assert_eq!(stats[2].0, ZoneFlags::DMA);
+    assert_eq!(stats[2].1.free_bytes, initial[2].1.free_bytes - 1024);
+    allocator.dealloc(p, layout(1024));
+    assert_eq!(allocator.stats().collect::<Vec<_>>(), initial);
+}
@@ -0,0 +1,25 @@
+//! Zeroize
+//! Wipes memory with writes that the compiler can not elide.
+
+use core::sync::atomic::{compiler_fence, Ordering};
+
+/// Fill `p..(p + len)` with zeros by volatile writes
+pub(crate) fn zeroize(p: *mut u8, len: usize) {
+    const WORD_SIZE: usize = core::mem::size_of::<usize>();
+    let mut i = 0;
+    unsafe {
+        // blocks are at least word aligned, wipe a word at a time
+        if p as usize & (WORD_SIZE - 1) == 0 {
+            while i + WORD_SIZE <= len {
+                core::ptr::write_volatile(p.add(i).cast::<usize>(), 0);
+                i += WORD_SIZE;
+            }
+        }
+        while i < len {
+            core::ptr::write_volatile(p.add(i), 0);
+            i += 1;
+        }
+    }
+    // prevent later writes, e.g. the free list node, from being reordered before wiping
+    compiler_fence(Ordering::SeqCst);
+}
@@ -0,0 +1,164 @@
+//! Zone alloc
+//! Memory zones of different attributes, e.g. DMA capable or tightly coupled memory,
+//! each zone is a buddy heap and requests are served by the zones with the required flags.
+
+use crate::buddy_alloc::{BuddyAlloc, BuddyAllocParam, Stats};
+use crate::raw_alloc::RawAlloc;
+use core::alloc::Layout;
+
+/// Max number of zones
+pub const MAX_ZONES: usize = 4;
+
+/// Attributes of a zone, a request can only be served by zones with all the required flags
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ZoneFlags(u32);
+
+impl ZoneFlags {
+    /// No attributes, every zone serves a request with no flags
+    pub const NONE: ZoneFlags = ZoneFlags(0);
+    /// Reachable by DMA
+    pub const DMA: ZoneFlags = ZoneFlags(1);
+    /// Fast memory, e.g. tightly coupled memory
+    pub const FAST: ZoneFlags = ZoneFlags(1 << 1);
+
+    pub const fn union(self, other: ZoneFlags) -> ZoneFlags {
+        ZoneFlags(self.0 | other.0)
+    }
+
+    /// Returns true if all flags of other are set
+    pub const fn contains(self, other: ZoneFlags) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl core::ops::BitOr for ZoneFlags {
+    type Output = ZoneFlags;
+
+    fn bitor(self, other: ZoneFlags) -> ZoneFlags {
+        self.union(other)
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct ZoneParam {
+    buddy_alloc_param: BuddyAllocParam,
+    flags: ZoneFlags,
+}
+
+impl ZoneParam {
+    /// Buddy alloc param: the heap of the zone
+    /// Flags: attributes of the zone
+    pub const fn new(buddy_alloc_param: BuddyAllocParam, flags: ZoneFlags) -> Self {
+        ZoneParam {
+            buddy_alloc_param,
+            flags,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct ZoneAllocParam {
+    zones: &'static [ZoneParam],
+}
+
+impl ZoneAllocParam {
+    /// Zones: at most `MAX_ZONES`, requests try the compatible zones in this order.
+    /// e.g. put the normal memory before the DMA zone, so requests without flags
+    /// only fall back to the DMA zone after the normal memory is exhausted.
+    pub const fn new(zones: &'static [ZoneParam]) -> Self {
+        ZoneAllocParam { zones }
+    }
+}
+
+struct Zone {
+    alloc: BuddyAlloc,
+    flags: ZoneFlags,
+}
+
+pub struct ZoneAlloc {
+    zones: [Option<Zone>; MAX_ZONES],
+}
+
+impl ZoneAlloc {
+    /// # Safety
+    ///
+    /// See `BuddyAlloc::new`, the memory ranges of zones must not overlap.
+    pub unsafe fn new(param: ZoneAllocParam) -> Self {
+        assert!(param.zones.len() <= MAX_ZONES, "too many zones");
+        let mut zones = [const { None }; MAX_ZONES];
+        for (zone, param) in zones.iter_mut().zip(param.zones) {
+            *zone = Some(Zone {
+                alloc: BuddyAlloc::new(param.buddy_alloc_param),
+                flags: param.flags,
+            });
+        }
+        ZoneAlloc { zones }
+    }
+
+    /// Allocate from the first zone which has the flags and can serve the layout,
+    /// returns null if there is no such zone.
+    pub fn alloc_in(&mut self, layout: Layout, flags: ZoneFlags) -> *mut u8 {
+        for zone in self.zones.iter_mut().flatten() {
+            if !zone.flags.contains(flags) {
+                continue;
+            }
+            let p = RawAlloc::malloc(&mut zone.alloc, layout);
+            if !p.is_null() {
+                return p;
+            }
+        }
+        core::ptr::null_mut()
+    }
+
+    /// Free a block to the zone it's inside
+    pub fn dealloc(&mut self, p: *mut u8, layout: Layout) {
+        let zone = self
+            .zones
+            .iter_mut()
+            .flatten()
+            .find(|zone| zone.alloc.contains_ptr(p))
+            .expect("free a block not belongs to ZoneAlloc");
+        RawAlloc::free(&mut zone.alloc, p, layout)
+    }
+
+    /// Returns the flags of the zone p is inside
+    pub fn zone_flags(&self, p: *const u8) -> Option<ZoneFlags> {
+        self.zone(p).map(|zone| zone.flags)
+    }
+
+    /// Returns the flags and the stats of each zone, in the order of zones
+    pub fn stats(&self) -> impl Iterator<Item = (ZoneFlags, Stats)> + '_ {
+        self.zones
+            .iter()
+            .flatten()
+            .map(|zone| (zone.flags, zone.alloc.stats()))
+    }
+
+    fn zone(&self, p: *const u8) -> Option<&Zone> {
+        self.zones
+            .iter()
+            .flatten()
+            .find(|zone| zone.alloc.contains_ptr(p))
+    }
+}
+
+/// Requests without flags, see `ZoneAlloc::alloc_in`
+impl RawAlloc for ZoneAlloc {
+    fn malloc(&mut self, layout: Layout) -> *mut u8 {
+        self.alloc_in(layout, ZoneFlags::NONE)
+    }
+
+    fn free(&mut self, p: *mut u8, layout: Layout) {
+        self.dealloc(p, layout)
+    }
+
+    fn contains(&self, p: *mut u8) -> bool {
+        self.zone(p).is_some()
+    }
+
+    fn usable_size(&self, p: *mut u8) -> usize {
+        self.zone(p)
+            .map(|zone| zone.alloc.usable_size(p))
+            .expect("block not belongs to ZoneAlloc")
+    }
+}